@@ -11,8 +11,8 @@ use {
     solana_pubkey::Pubkey,
     solana_sbpf::memory_region::{AccessType, AccessViolationHandler, MemoryRegion},
     std::{
-        cell::{Ref, RefCell, RefMut},
-        collections::HashSet,
+        cell::{Cell, Ref, RefCell, RefMut},
+        collections::{HashMap, HashSet},
         pin::Pin,
         rc::Rc,
     },
@@ -49,6 +49,20 @@ static_assertions::const_assert_eq!(
     solana_account_info::MAX_PERMITTED_DATA_INCREASE
 );
 
+/// Maximum length of the data parameter of an Instruction, see `InstructionContext::try_configure()`
+///
+/// Matches the CPI limit, top level instructions are additionally bounded by the packet size.
+pub const MAX_INSTRUCTION_DATA_LEN: usize = 10 * 1024;
+
+/// Maximum length of the return data, see `TransactionContext::set_return_data()`
+///
+/// Matches `solana_cpi::MAX_RETURN_DATA`.
+pub const MAX_RETURN_DATA: usize = 1024;
+
+/// Bits of the packed privileges, see `InstructionContext::export_account_privileges()`
+const SIGNER_PRIVILEGE: u8 = 1 << 0;
+const WRITABLE_PRIVILEGE: u8 = 1 << 1;
+
 /// Index of an account inside of the TransactionContext or an InstructionContext.
 pub type IndexOfAccount = u16;
 
@@ -56,6 +70,10 @@ pub type IndexOfAccount = u16;
 ///
 /// It also contains indices to other structures for faster lookup.
 #[repr(C)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde_derive::Deserialize, serde_derive::Serialize)
+)]
 #[derive(Clone, Debug, Eq, PartialEq)]
 pub struct InstructionAccount {
     /// Points to the account and its key in the `TransactionContext`
@@ -110,16 +128,54 @@ pub struct TransactionAccounts {
     accounts: Vec<RefCell<AccountSharedData>>,
     touched_flags: RefCell<Box<[bool]>>,
     resize_delta: RefCell<i64>,
+    peak_resize_delta: Cell<i64>,
+    original_data_lengths: Box<[usize]>,
+    /// Lamports from before the first `BorrowedAccount::set_lamports()` of each account,
+    /// allocated on the first change and `None` for accounts which were not changed
+    original_lamports: RefCell<Option<Box<[Option<u64>]>>>,
+    /// Limits set via `TransactionContext::set_account_max_length()`, allocated on first use
+    max_data_lengths: RefCell<Option<Box<[usize]>>>,
+    #[cfg(any(test, feature = "dev-context-only-utils"))]
+    borrowed_flags: RefCell<Box<[bool]>>,
+    #[cfg(any(test, feature = "dev-context-only-utils"))]
+    read_access_order: RefCell<Option<Vec<IndexOfAccount>>>,
+    /// Which accounts `read_access_order` already contains
+    #[cfg(any(test, feature = "dev-context-only-utils"))]
+    read_access_recorded_flags: RefCell<Box<[bool]>>,
+    #[cfg(any(test, feature = "dev-context-only-utils"))]
+    read_flags: RefCell<Box<[bool]>>,
 }
 
 impl TransactionAccounts {
     #[cfg(not(target_os = "solana"))]
     fn new(accounts: Vec<RefCell<AccountSharedData>>) -> TransactionAccounts {
         let touched_flags = vec![false; accounts.len()].into_boxed_slice();
+        let original_data_lengths = accounts
+            .iter()
+            .map(|account| account.borrow().data().len())
+            .collect();
+        #[cfg(any(test, feature = "dev-context-only-utils"))]
+        let borrowed_flags = vec![false; accounts.len()].into_boxed_slice();
+        #[cfg(any(test, feature = "dev-context-only-utils"))]
+        let read_flags = vec![false; accounts.len()].into_boxed_slice();
+        #[cfg(any(test, feature = "dev-context-only-utils"))]
+        let read_access_recorded_flags = vec![false; accounts.len()].into_boxed_slice();
         TransactionAccounts {
             accounts,
             touched_flags: RefCell::new(touched_flags),
             resize_delta: RefCell::new(0),
+            peak_resize_delta: Cell::new(0),
+            original_data_lengths,
+            original_lamports: RefCell::new(None),
+            max_data_lengths: RefCell::new(None),
+            #[cfg(any(test, feature = "dev-context-only-utils"))]
+            borrowed_flags: RefCell::new(borrowed_flags),
+            #[cfg(any(test, feature = "dev-context-only-utils"))]
+            read_access_order: RefCell::new(None),
+            #[cfg(any(test, feature = "dev-context-only-utils"))]
+            read_access_recorded_flags: RefCell::new(read_access_recorded_flags),
+            #[cfg(any(test, feature = "dev-context-only-utils"))]
+            read_flags: RefCell::new(read_flags),
         }
     }
 
@@ -141,6 +197,83 @@ impl TransactionAccounts {
         Ok(())
     }
 
+    /// Touches all the given accounts, or none if any index is out of range
+    #[cfg(not(target_os = "solana"))]
+    pub fn touch_all(&self, indices: &[IndexOfAccount]) -> Result<(), InstructionError> {
+        let mut touched_flags = self.touched_flags.borrow_mut();
+        if indices
+            .iter()
+            .any(|index| *index as usize >= touched_flags.len())
+        {
+            return Err(InstructionError::NotEnoughAccountKeys);
+        }
+        for index in indices {
+            *touched_flags
+                .get_mut(*index as usize)
+                .ok_or(InstructionError::NotEnoughAccountKeys)? = true;
+        }
+        Ok(())
+    }
+
+    #[cfg(not(target_os = "solana"))]
+    pub fn is_touched(&self, index: IndexOfAccount) -> Result<bool, InstructionError> {
+        self.touched_flags
+            .try_borrow()
+            .map_err(|_| InstructionError::GenericError)?
+            .get(index as usize)
+            .copied()
+            .ok_or(InstructionError::NotEnoughAccountKeys)
+    }
+
+    #[cfg(any(test, feature = "dev-context-only-utils"))]
+    fn mark_borrowed(&self, index: IndexOfAccount) -> Result<(), InstructionError> {
+        *self
+            .borrowed_flags
+            .try_borrow_mut()
+            .map_err(|_| InstructionError::GenericError)?
+            .get_mut(index as usize)
+            .ok_or(InstructionError::NotEnoughAccountKeys)? = true;
+        Ok(())
+    }
+
+    #[cfg(any(test, feature = "dev-context-only-utils"))]
+    fn mark_read(&self, index: IndexOfAccount) {
+        if let Some(was_read) = self
+            .read_flags
+            .try_borrow_mut()
+            .ok()
+            .as_mut()
+            .and_then(|read_flags| read_flags.get_mut(index as usize))
+        {
+            *was_read = true;
+        }
+        self.record_read_access(index);
+    }
+
+    #[cfg(any(test, feature = "dev-context-only-utils"))]
+    fn record_read_access(&self, index: IndexOfAccount) {
+        let Ok(mut read_access_order) = self.read_access_order.try_borrow_mut() else {
+            return;
+        };
+        let Some(read_access_order) = read_access_order.as_mut() else {
+            return;
+        };
+        if let Some(was_recorded) = self
+            .read_access_recorded_flags
+            .try_borrow_mut()
+            .ok()
+            .as_mut()
+            .and_then(|read_access_recorded_flags| {
+                read_access_recorded_flags.get_mut(index as usize)
+            })
+        {
+            if !*was_recorded {
+                *was_recorded = true;
+                read_access_order.push(index);
+            }
+        }
+    }
+
     fn update_accounts_resize_delta(
         &self,
         old_len: usize,
@@ -152,22 +285,117 @@ impl TransactionAccounts {
             .map_err(|_| InstructionError::GenericError)?;
         *accounts_resize_delta =
             accounts_resize_delta.saturating_add((new_len as i64).saturating_sub(old_len as i64));
+        self.peak_resize_delta
+            .set(self.peak_resize_delta.get().max(*accounts_resize_delta));
+        Ok(())
+    }
+
+    fn remaining_resize_budget(&self) -> Result<i64, InstructionError> {
+        self.resize_delta
+            .try_borrow()
+            .map_err(|_| InstructionError::GenericError)
+            .map(|resize_delta| {
+                MAX_PERMITTED_ACCOUNTS_DATA_ALLOCATIONS_PER_TRANSACTION
+                    .saturating_sub(*resize_delta)
+                    .max(0)
+            })
+    }
+
+    #[cfg(not(target_os = "solana"))]
+    fn set_max_data_length(
+        &self,
+        index: IndexOfAccount,
+        max_data_length: usize,
+    ) -> Result<(), InstructionError> {
+        if index as usize >= self.accounts.len() {
+            return Err(InstructionError::NotEnoughAccountKeys);
+        }
+        *self
+            .max_data_lengths
+            .try_borrow_mut()
+            .map_err(|_| InstructionError::GenericError)?
+            .get_or_insert_with(|| {
+                vec![MAX_PERMITTED_DATA_LENGTH as usize; self.accounts.len()].into_boxed_slice()
+            })
+            .get_mut(index as usize)
+            .ok_or(InstructionError::NotEnoughAccountKeys)? = max_data_length;
+        Ok(())
+    }
+
+    fn max_data_length(&self, index: IndexOfAccount) -> Result<usize, InstructionError> {
+        match self
+            .max_data_lengths
+            .try_borrow()
+            .map_err(|_| InstructionError::GenericError)?
+            .as_ref()
+        {
+            Some(max_data_lengths) => max_data_lengths
+                .get(index as usize)
+                .copied()
+                .ok_or(InstructionError::NotEnoughAccountKeys),
+            None => Ok(MAX_PERMITTED_DATA_LENGTH as usize),
+        }
+    }
+
+    #[cfg(not(target_os = "solana"))]
+    fn record_original_lamports(
+        &self,
+        index: IndexOfAccount,
+        lamports: u64,
+    ) -> Result<(), InstructionError> {
+        self.original_lamports
+            .try_borrow_mut()
+            .map_err(|_| InstructionError::GenericError)?
+            .get_or_insert_with(|| vec![None; self.accounts.len()].into_boxed_slice())
+            .get_mut(index as usize)
+            .ok_or(InstructionError::NotEnoughAccountKeys)?
+            .get_or_insert(lamports);
         Ok(())
     }
 
-    fn can_data_be_resized(&self, old_len: usize, new_len: usize) -> Result<(), InstructionError> {
+    #[cfg(not(target_os = "solana"))]
+    fn original_lamports(&self, index: IndexOfAccount) -> Result<Option<u64>, InstructionError> {
+        Ok(self
+            .original_lamports
+            .try_borrow()
+            .map_err(|_| InstructionError::GenericError)?
+            .as_ref()
+            .and_then(|original_lamports| original_lamports.get(index as usize).copied())
+            .flatten())
+    }
+
+    fn can_data_be_resized(
+        &self,
+        index: IndexOfAccount,
+        old_len: usize,
+        new_len: usize,
+    ) -> Result<(), InstructionError> {
+        let resize_delta = self
+            .resize_delta
+            .try_borrow()
+            .map_err(|_| InstructionError::GenericError)
+            .map(|value_ref| *value_ref)?;
+        self.can_data_be_resized_with_delta(resize_delta, index, old_len, new_len)
+    }
+
+    fn can_data_be_resized_with_delta(
+        &self,
+        resize_delta: i64,
+        index: IndexOfAccount,
+        old_len: usize,
+        new_len: usize,
+    ) -> Result<(), InstructionError> {
         // The new length can not exceed the maximum permitted length
         if new_len > MAX_PERMITTED_DATA_LENGTH as usize {
             return Err(InstructionError::InvalidRealloc);
         }
+        // The account can not grow beyond its own maximum length
+        if new_len > old_len && new_len > self.max_data_length(index)? {
+            return Err(InstructionError::InvalidRealloc);
+        }
         // The resize can not exceed the per-transaction maximum
         let length_delta = (new_len as i64).saturating_sub(old_len as i64);
-        if self
-            .resize_delta
-            .try_borrow()
-            .map_err(|_| InstructionError::GenericError)
-            .map(|value_ref| *value_ref)?
-            .saturating_add(length_delta)
+        if resize_delta.saturating_add(length_delta)
             > MAX_PERMITTED_ACCOUNTS_DATA_ALLOCATIONS_PER_TRANSACTION
         {
             return Err(InstructionError::MaxAccountsDataAllocationsExceeded);
@@ -204,10 +432,21 @@ pub struct TransactionContext {
     remove_accounts_executable_flag_checks: bool,
     #[cfg(not(target_os = "solana"))]
     rent: Rent,
+    #[cfg(all(
+        not(target_os = "solana"),
+        any(test, feature = "dev-context-only-utils")
+    ))]
+    replayed_instructions: HashSet<usize>,
+    #[cfg(not(target_os = "solana"))]
+    stack_observer: Option<Observer<dyn Fn(StackEvent)>>,
+    #[cfg(not(target_os = "solana"))]
+    trace_sink: Option<Observer<TraceSink>>,
 }
 
 impl TransactionContext {
     /// Constructs a new TransactionContext
+    ///
+    /// Panics if there are more accounts than `IndexOfAccount` can address, see `try_new()`.
     #[cfg(not(target_os = "solana"))]
     pub fn new(
         transaction_accounts: Vec<TransactionAccount>,
@@ -215,11 +454,34 @@ impl TransactionContext {
         instruction_stack_capacity: usize,
         instruction_trace_capacity: usize,
     ) -> Self {
+        Self::try_new(
+            transaction_accounts,
+            rent,
+            instruction_stack_capacity,
+            instruction_trace_capacity,
+        )
+        .expect("too many transaction accounts")
+    }
+
+    /// Constructs a new TransactionContext
+    ///
+    /// Fails with `MaxAccountsExceeded` if there are more accounts than `IndexOfAccount` can
+    /// address.
+    #[cfg(not(target_os = "solana"))]
+    pub fn try_new(
+        transaction_accounts: Vec<TransactionAccount>,
+        rent: Rent,
+        instruction_stack_capacity: usize,
+        instruction_trace_capacity: usize,
+    ) -> Result<Self, InstructionError> {
+        if transaction_accounts.len() > IndexOfAccount::MAX as usize {
+            return Err(InstructionError::MaxAccountsExceeded);
+        }
         let (account_keys, accounts): (Vec<_>, Vec<_>) = transaction_accounts
             .into_iter()
             .map(|(key, account)| (key, RefCell::new(account)))
             .unzip();
-        Self {
+        Ok(Self {
             account_keys: Pin::new(account_keys.into_boxed_slice()),
             accounts: Rc::new(TransactionAccounts::new(accounts)),
             instruction_stack_capacity,
@@ -230,7 +492,11 @@ impl TransactionContext {
             return_data: TransactionReturnData::default(),
             remove_accounts_executable_flag_checks: true,
             rent,
-        }
+            #[cfg(any(test, feature = "dev-context-only-utils"))]
+            replayed_instructions: HashSet::new(),
+            stack_observer: None,
+            trace_sink: None,
+        })
     }
 
     #[cfg(not(target_os = "solana"))]
@@ -238,6 +504,49 @@ impl TransactionContext {
         self.remove_accounts_executable_flag_checks = enabled;
     }
 
+    /// Overwrites the rent parameters used by this context
+    #[cfg(all(
+        not(target_os = "solana"),
+        any(test, feature = "dev-context-only-utils")
+    ))]
+    pub fn set_rent(&mut self, rent: Rent) {
+        self.rent = rent;
+    }
+
+    /// Marks the InstructionContext at the given index in the trace as being replayed
+    ///
+    /// Used by simulators which re-execute a slice of a transaction.
+    #[cfg(all(
+        not(target_os = "solana"),
+        any(test, feature = "dev-context-only-utils")
+    ))]
+    pub fn mark_instruction_replayed(&mut self, index_in_trace: usize) {
+        self.replayed_instructions.insert(index_in_trace);
+    }
+
+    /// Returns whether the InstructionContext at the given index in the trace is being replayed
+    #[cfg(all(
+        not(target_os = "solana"),
+        any(test, feature = "dev-context-only-utils")
+    ))]
+    pub fn is_instruction_replayed(&self, index_in_trace: usize) -> bool {
+        self.replayed_instructions.contains(&index_in_trace)
+    }
+
+    /// Registers a callback which is invoked for every push and pop of an InstructionContext
+    #[cfg(not(target_os = "solana"))]
+    pub fn set_stack_observer(&mut self, observer: Box<dyn Fn(StackEvent)>) {
+        self.stack_observer = Some(Observer(Rc::from(observer)));
+    }
+
+    /// Registers a callback which receives every InstructionContext once it is popped
+    ///
+    /// The instruction trace is still recorded as usual.
+    #[cfg(not(target_os = "solana"))]
+    pub fn set_trace_sink(&mut self, sink: Box<dyn FnMut(&InstructionContext)>) {
+        self.trace_sink = Some(Observer(Rc::new(RefCell::new(sink))));
+    }
+
     /// Used in mock_process_instruction
     #[cfg(not(target_os = "solana"))]
     pub fn deconstruct_without_keys(self) -> Result<Vec<AccountSharedData>, InstructionError> {
@@ -253,6 +562,34 @@ impl TransactionContext {
             .collect())
     }
 
+    /// Clones the keys and current state of all accounts, without consuming this context
+    ///
+    /// Unlike `deconstruct_without_keys()` this does not require the instruction stack to be
+    /// empty, so it can be used to seed another TransactionContext mid-execution.
+    #[cfg(not(target_os = "solana"))]
+    pub fn clone_transaction_accounts(&self) -> Result<Vec<TransactionAccount>, InstructionError> {
+        self.account_keys
+            .iter()
+            .enumerate()
+            .map(|(index_in_transaction, key)| {
+                self.accounts
+                    .try_borrow(index_in_transaction as IndexOfAccount)
+                    .map(|account| {
+                        // Copy the data instead of sharing it, which would make the account
+                        // in this context appear as shared (see `BorrowedAccount::is_shared()`).
+                        let account = AccountSharedData::create(
+                            account.lamports(),
+                            account.data().to_vec(),
+                            *account.owner(),
+                            account.executable(),
+                            account.rent_epoch(),
+                        );
+                        (*key, account)
+                    })
+            })
+            .collect()
+    }
+
     #[cfg(not(target_os = "solana"))]
     pub fn accounts(&self) -> &Rc<TransactionAccounts> {
         &self.accounts
@@ -273,6 +610,47 @@ impl TransactionContext {
             .ok_or(InstructionError::NotEnoughAccountKeys)
     }
 
+    /// Borrows an account read-only, without an InstructionContext
+    pub fn peek_account(
+        &self,
+        index_in_transaction: IndexOfAccount,
+    ) -> Result<Ref<'_, AccountSharedData>, InstructionError> {
+        self.accounts
+            .get(index_in_transaction)
+            .ok_or(InstructionError::NotEnoughAccountKeys)?
+            .try_borrow()
+            .map_err(|_| InstructionError::AccountBorrowFailed)
+    }
+
+    /// Borrows an account mutably, without an InstructionContext
+    ///
+    /// Meant for trusted runtime code in between instructions. None of the checks of
+    /// `BorrowedAccount` apply and the account is not marked as touched.
+    #[cfg(not(target_os = "solana"))]
+    pub fn try_borrow_account_mut_by_index(
+        &self,
+        index_in_transaction: IndexOfAccount,
+    ) -> Result<RefMut<'_, AccountSharedData>, InstructionError> {
+        self.accounts
+            .get(index_in_transaction)
+            .ok_or(InstructionError::NotEnoughAccountKeys)?
+            .try_borrow_mut()
+            .map_err(|_| InstructionError::AccountBorrowFailed)
+    }
+
+    /// Returns the key and owner of every account in this Transaction
+    pub fn key_owner_pairs(&self) -> Result<Vec<(Pubkey, Pubkey)>, InstructionError> {
+        self.account_keys
+            .iter()
+            .enumerate()
+            .map(|(index_in_transaction, key)| {
+                self.accounts
+                    .try_borrow(index_in_transaction as IndexOfAccount)
+                    .map(|account| (*key, *account.owner()))
+            })
+            .collect()
+    }
+
     /// Searches for an account by its key
     #[cfg(all(
         not(target_os = "solana"),
@@ -288,6 +666,8 @@ impl TransactionContext {
     }
 
     /// Searches for an account by its key
+    ///
+    /// Returns the index of the first occurrence if the key appears more than once.
     pub fn find_index_of_account(&self, pubkey: &Pubkey) -> Option<IndexOfAccount> {
         self.account_keys
             .iter()
@@ -295,52 +675,464 @@ impl TransactionContext {
             .map(|index| index as IndexOfAccount)
     }
 
-    /// Searches for a program account by its key
-    pub fn find_index_of_program_account(&self, pubkey: &Pubkey) -> Option<IndexOfAccount> {
-        self.account_keys
-            .iter()
-            .rposition(|key| key == pubkey)
-            .map(|index| index as IndexOfAccount)
-    }
-
-    /// Gets the max length of the InstructionContext trace
-    pub fn get_instruction_trace_capacity(&self) -> usize {
-        self.instruction_trace_capacity
+    /// Copies the data of one instruction account of the current Instruction into another one
+    ///
+    /// The destination is resized as needed and must pass the same checks as
+    /// `BorrowedAccount::set_data_from_slice()`. Copying an account onto itself is a no-op.
+    #[cfg(not(target_os = "solana"))]
+    pub fn copy_account_data(
+        &self,
+        source_instruction_account_index: IndexOfAccount,
+        destination_instruction_account_index: IndexOfAccount,
+    ) -> Result<(), InstructionError> {
+        let instruction_context = self.get_current_instruction_context()?;
+        if instruction_context
+            .get_index_of_instruction_account_in_transaction(source_instruction_account_index)?
+            == instruction_context.get_index_of_instruction_account_in_transaction(
+                destination_instruction_account_index,
+            )?
+        {
+            return Ok(());
+        }
+        let source = instruction_context
+            .try_borrow_instruction_account(self, source_instruction_account_index)?;
+        let mut destination = instruction_context
+            .try_borrow_instruction_account(self, destination_instruction_account_index)?;
+        destination.set_data_from_slice(source.get_data())
     }
 
-    /// Returns the instruction trace length.
+    /// Overwrites the lamports of several instruction accounts of the current Instruction
     ///
-    /// Not counting the last empty InstructionContext which is always pre-reserved for the next instruction.
-    /// See also `get_next_instruction_context()`.
-    pub fn get_instruction_trace_length(&self) -> usize {
-        self.instruction_trace.len().saturating_sub(1)
+    /// Every update is checked the same way `BorrowedAccount::set_lamports()` checks it
+    /// before any of them is applied, so either all accounts are updated or none are.
+    /// Each instruction account may only appear once in `updates`.
+    #[cfg(not(target_os = "solana"))]
+    pub fn set_lamports_batch(
+        &self,
+        updates: &[(IndexOfAccount, u64)],
+    ) -> Result<(), InstructionError> {
+        let mut seen = HashSet::with_capacity(updates.len());
+        if !updates
+            .iter()
+            .all(|(instruction_account_index, _)| seen.insert(*instruction_account_index))
+        {
+            return Err(InstructionError::DuplicateAccountIndex);
+        }
+        let instruction_context = self.get_current_instruction_context()?;
+        let mut accounts = updates
+            .iter()
+            .map(|(instruction_account_index, lamports)| {
+                let account = instruction_context
+                    .try_borrow_instruction_account(self, *instruction_account_index)?;
+                account.can_lamports_be_changed(*lamports)?;
+                Ok((account, *lamports))
+            })
+            .collect::<Result<Vec<_>, InstructionError>>()?;
+        for (account, lamports) in accounts.iter_mut() {
+            account.set_lamports(*lamports)?;
+        }
+        Ok(())
     }
 
-    /// Gets an InstructionContext by its index in the trace
-    pub fn get_instruction_context_at_index_in_trace(
+    /// Borrows the instructions sysvar account read-only
+    ///
+    /// Validates the owner the same way `push()` does.
+    #[cfg(not(target_os = "solana"))]
+    pub fn try_borrow_instructions_sysvar(
         &self,
-        index_in_trace: usize,
-    ) -> Result<&InstructionContext, InstructionError> {
-        self.instruction_trace
-            .get(index_in_trace)
-            .ok_or(InstructionError::CallDepth)
+    ) -> Result<Ref<'_, AccountSharedData>, InstructionError> {
+        let index_in_transaction = self
+            .find_index_of_account(&instructions::id())
+            .ok_or(InstructionError::MissingAccount)?;
+        let account = self.accounts.try_borrow(index_in_transaction)?;
+        if account.owner() != &solana_sdk_ids::sysvar::id() {
+            return Err(InstructionError::InvalidAccountOwner);
+        }
+        Ok(account)
     }
 
-    /// Gets an InstructionContext by its nesting level in the stack
-    pub fn get_instruction_context_at_nesting_level(
-        &self,
-        nesting_level: usize,
-    ) -> Result<&InstructionContext, InstructionError> {
-        let index_in_trace = *self
-            .instruction_stack
-            .get(nesting_level)
-            .ok_or(InstructionError::CallDepth)?;
-        let instruction_context = self.get_instruction_context_at_index_in_trace(index_in_trace)?;
-        debug_assert_eq!(instruction_context.nesting_level, nesting_level);
-        Ok(instruction_context)
+    /// Returns the indices of all accounts which were never borrowed by an instruction
+    ///
+    /// These accounts could potentially be removed from the message.
+    #[cfg(all(
+        not(target_os = "solana"),
+        any(test, feature = "dev-context-only-utils")
+    ))]
+    pub fn unborrowed_account_indices(&self) -> Result<Vec<IndexOfAccount>, InstructionError> {
+        Ok(self
+            .accounts
+            .borrowed_flags
+            .try_borrow()
+            .map_err(|_| InstructionError::GenericError)?
+            .iter()
+            .enumerate()
+            .filter(|(_, was_borrowed)| !**was_borrowed)
+            .map(|(index_in_transaction, _)| index_in_transaction as IndexOfAccount)
+            .collect())
     }
 
-    /// Gets the max height of the InstructionContext stack
+    /// Starts or stops recording the order in which accounts are first read
+    ///
+    /// Recording is off by default. Starting it discards previously recorded accesses.
+    #[cfg(all(
+        not(target_os = "solana"),
+        any(test, feature = "dev-context-only-utils")
+    ))]
+    pub fn set_record_read_accesses(&self, enabled: bool) -> Result<(), InstructionError> {
+        *self
+            .accounts
+            .read_access_order
+            .try_borrow_mut()
+            .map_err(|_| InstructionError::GenericError)? = enabled.then(Vec::new);
+        self.accounts
+            .read_access_recorded_flags
+            .try_borrow_mut()
+            .map_err(|_| InstructionError::GenericError)?
+            .fill(false);
+        Ok(())
+    }
+
+    /// Returns the accounts read by instructions, in the order of their first read
+    ///
+    /// Reads are the same as for `account_access_kind()`, borrowing an account without looking
+    /// at its data does not count. This is empty unless recording was enabled via
+    /// `set_record_read_accesses()`.
+    #[cfg(all(
+        not(target_os = "solana"),
+        any(test, feature = "dev-context-only-utils")
+    ))]
+    pub fn read_access_order(&self) -> Result<Vec<IndexOfAccount>, InstructionError> {
+        Ok(self
+            .accounts
+            .read_access_order
+            .try_borrow()
+            .map_err(|_| InstructionError::GenericError)?
+            .clone()
+            .unwrap_or_default())
+    }
+
+    /// Returns whether an account was read and / or written so far in this Transaction
+    ///
+    /// Reads are tracked in `BorrowedAccount::get_data()` and `BorrowedAccount::get_state()`,
+    /// which costs one extra flag update per call in builds with `dev-context-only-utils`. Writes are the accounts touched by any
+    /// mutation. Reading the lamports, owner or length of the data is not counted as a read.
+    #[cfg(all(
+        not(target_os = "solana"),
+        any(test, feature = "dev-context-only-utils")
+    ))]
+    pub fn account_access_kind(
+        &self,
+        index_in_transaction: IndexOfAccount,
+    ) -> Result<AccessKind, InstructionError> {
+        let was_read = *self
+            .accounts
+            .read_flags
+            .try_borrow()
+            .map_err(|_| InstructionError::GenericError)?
+            .get(index_in_transaction as usize)
+            .ok_or(InstructionError::NotEnoughAccountKeys)?;
+        let was_written = self.accounts.is_touched(index_in_transaction)?;
+        Ok(match (was_read, was_written) {
+            (false, false) => AccessKind::None,
+            (true, false) => AccessKind::ReadOnly,
+            (false, true) => AccessKind::WriteOnly,
+            (true, true) => AccessKind::ReadWrite,
+        })
+    }
+
+    /// Searches for a program account by its key
+    ///
+    /// Returns the index of the last occurrence if the key appears more than once.
+    pub fn find_index_of_program_account(&self, pubkey: &Pubkey) -> Option<IndexOfAccount> {
+        self.account_keys
+            .iter()
+            .rposition(|key| key == pubkey)
+            .map(|index| index as IndexOfAccount)
+    }
+
+    /// Gets the max length of the InstructionContext trace
+    pub fn get_instruction_trace_capacity(&self) -> usize {
+        self.instruction_trace_capacity
+    }
+
+    /// Returns the instruction trace length.
+    ///
+    /// Not counting the last empty InstructionContext which is always pre-reserved for the next instruction.
+    /// See also `get_next_instruction_context()`.
+    pub fn get_instruction_trace_length(&self) -> usize {
+        self.instruction_trace.len().saturating_sub(1)
+    }
+
+    /// Gets an InstructionContext by its index in the trace
+    pub fn get_instruction_context_at_index_in_trace(
+        &self,
+        index_in_trace: usize,
+    ) -> Result<&InstructionContext, InstructionError> {
+        self.instruction_trace
+            .get(index_in_trace)
+            .ok_or(InstructionError::CallDepth)
+    }
+
+    /// Gets an InstructionContext by its nesting level in the stack
+    pub fn get_instruction_context_at_nesting_level(
+        &self,
+        nesting_level: usize,
+    ) -> Result<&InstructionContext, InstructionError> {
+        let index_in_trace = *self
+            .instruction_stack
+            .get(nesting_level)
+            .ok_or(InstructionError::CallDepth)?;
+        let instruction_context = self.get_instruction_context_at_index_in_trace(index_in_trace)?;
+        debug_assert_eq!(instruction_context.nesting_level, nesting_level);
+        Ok(instruction_context)
+    }
+
+    /// Checks the consistency of the instruction stack and trace
+    ///
+    /// Fails with the error of the first violated invariant:
+    /// - `GenericError` if the pre-reserved InstructionContext at the end of the trace is missing
+    /// - `MaxInstructionTraceLengthExceeded` if the trace exceeds its capacity
+    /// - `CallDepth` if the stack exceeds its capacity, if a stacked index does not point to
+    ///   a pushed InstructionContext in the trace or if its nesting level does not match
+    ///
+    /// The pre-reserved InstructionContext may already be configured for the next `push()`.
+    pub fn validate_trace(&self) -> Result<(), InstructionError> {
+        if self.instruction_trace.is_empty() {
+            return Err(InstructionError::GenericError);
+        }
+        let trace_length = self.get_instruction_trace_length();
+        if trace_length > self.instruction_trace_capacity {
+            return Err(InstructionError::MaxInstructionTraceLengthExceeded);
+        }
+        if self.instruction_stack.len() > self.instruction_stack_capacity {
+            return Err(InstructionError::CallDepth);
+        }
+        let mut previous_index_in_trace = None;
+        for (nesting_level, index_in_trace) in self.instruction_stack.iter().enumerate() {
+            if *index_in_trace >= trace_length
+                || previous_index_in_trace.is_some_and(|previous| previous >= *index_in_trace)
+                || self
+                    .get_instruction_context_at_index_in_trace(*index_in_trace)?
+                    .nesting_level
+                    != nesting_level
+            {
+                return Err(InstructionError::CallDepth);
+            }
+            previous_index_in_trace = Some(*index_in_trace);
+        }
+        Ok(())
+    }
+
+    /// Returns whether the given key is a signer in any InstructionContext of the trace
+    pub fn is_signer_anywhere(&self, pubkey: &Pubkey) -> Result<bool, InstructionError> {
+        for instruction_context in self
+            .instruction_trace
+            .iter()
+            .take(self.get_instruction_trace_length())
+        {
+            for instruction_account in instruction_context.instruction_accounts.iter() {
+                if instruction_account.is_signer()
+                    && self.get_key_of_account_at_index(instruction_account.index_in_transaction)?
+                        == pubkey
+                {
+                    return Ok(true);
+                }
+            }
+        }
+        Ok(false)
+    }
+
+    /// Packs which accounts are a signer in any InstructionContext of the trace into a bitset
+    ///
+    /// Bit `i % 64` of word `i / 64` corresponds to the account at index `i` in the transaction,
+    /// see `is_bit_set()`.
+    pub fn signer_bitset(&self) -> Result<Vec<u64>, InstructionError> {
+        let number_of_accounts = self.get_number_of_accounts();
+        let mut signers = vec![0u64; (number_of_accounts as usize).div_ceil(64)];
+        for instruction_context in self
+            .instruction_trace
+            .iter()
+            .take(self.get_instruction_trace_length())
+        {
+            for instruction_account in instruction_context.instruction_accounts.iter() {
+                if !instruction_account.is_signer() {
+                    continue;
+                }
+                let index_in_transaction = instruction_account.index_in_transaction;
+                if index_in_transaction >= number_of_accounts {
+                    return Err(InstructionError::NotEnoughAccountKeys);
+                }
+                if let Some(word) = signers.get_mut(index_in_transaction as usize / 64) {
+                    *word |= 1u64 << (index_in_transaction % 64);
+                }
+            }
+        }
+        Ok(signers)
+    }
+
+    /// Returns whether the bit for the given index is set in a bitset from `signer_bitset()`
+    pub fn is_bit_set(bitset: &[u64], index: IndexOfAccount) -> bool {
+        bitset
+            .get(index as usize / 64)
+            .is_some_and(|word| word & (1u64 << (index % 64)) != 0)
+    }
+
+    /// Returns the keys of all accounts which are writable in any InstructionContext of the trace
+    ///
+    /// The keys are deduplicated and sorted, which is the canonical order for acquiring locks.
+    pub fn writable_account_keys_sorted(&self) -> Result<Vec<Pubkey>, InstructionError> {
+        let mut writable_account_keys = self
+            .writable_account_indices()
+            .into_iter()
+            .map(|index_in_transaction| {
+                self.get_key_of_account_at_index(index_in_transaction)
+                    .copied()
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        writable_account_keys.sort_unstable();
+        Ok(writable_account_keys)
+    }
+
+    /// Reserves `additional` bytes of capacity in every account writable anywhere in the trace
+    ///
+    /// This only affects the capacity of the data buffers, not their length nor the resize delta.
+    /// The reservation is capped by how much each account could still grow in this Transaction.
+    ///
+    /// Reserving in an account which is still shared allocates a new buffer and copies the whole
+    /// data into it, so afterwards the account is no longer shared (see
+    /// `BorrowedAccount::is_shared()` and `count_shared_accounts()`). Depending on how many of
+    /// these accounts would grow anyway, this can cost more than it saves.
+    ///
+    /// Fails with `AccountBorrowFailed` if any of these accounts is currently borrowed, including
+    /// accounts of instructions which already finished. Accounts reserved before the failure keep
+    /// their capacity.
+    #[cfg(not(target_os = "solana"))]
+    pub fn reserve_writable_capacity(&self, additional: usize) -> Result<(), InstructionError> {
+        let remaining_allowed_growth = self.remaining_resize_budget()? as usize;
+        for index_in_transaction in self.writable_account_indices() {
+            let max_data_length = self.accounts.max_data_length(index_in_transaction)?;
+            let mut account = self
+                .accounts
+                .get(index_in_transaction)
+                .ok_or(InstructionError::NotEnoughAccountKeys)?
+                .try_borrow_mut()
+                .map_err(|_| InstructionError::AccountBorrowFailed)?;
+            let additional = additional
+                .min(remaining_allowed_growth)
+                .min(max_data_length.saturating_sub(account.data().len()));
+            if additional > 0 {
+                account.reserve(additional);
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns the deduplicated and sorted indices of all program accounts in the trace
+    pub fn all_program_account_indices(&self) -> Vec<IndexOfAccount> {
+        let mut program_account_indices = self
+            .instruction_trace
+            .iter()
+            .take(self.get_instruction_trace_length())
+            .flat_map(|instruction_context| instruction_context.program_accounts.iter())
+            .copied()
+            .collect::<Vec<_>>();
+        program_account_indices.sort_unstable();
+        program_account_indices.dedup();
+        program_account_indices
+    }
+
+    /// Returns the deduplicated and sorted indices of all accounts writable anywhere in the trace
+    fn writable_account_indices(&self) -> Vec<IndexOfAccount> {
+        let mut writable_account_indices = self
+            .instruction_trace
+            .iter()
+            .take(self.get_instruction_trace_length())
+            .flat_map(|instruction_context| instruction_context.instruction_accounts.iter())
+            .filter(|instruction_account| instruction_account.is_writable())
+            .map(|instruction_account| instruction_account.index_in_transaction)
+            .collect::<Vec<_>>();
+        writable_account_indices.sort_unstable();
+        writable_account_indices.dedup();
+        writable_account_indices
+    }
+
+    /// Counts how often the given program was invoked via CPI in the trace
+    pub fn cpi_count_for_program(&self, program_id: &Pubkey) -> usize {
+        self.instruction_trace
+            .iter()
+            .take(self.get_instruction_trace_length())
+            .filter(|instruction_context| {
+                instruction_context.nesting_level > 0
+                    && instruction_context.get_last_program_key(self) == Ok(program_id)
+            })
+            .count()
+    }
+
+    /// Iterates over the InstructionContext trace in execution order
+    ///
+    /// Yields `(nesting_level, instruction_context)` pairs, where top level instructions
+    /// have a nesting level of 0.
+    pub fn trace_with_depth(&self) -> impl Iterator<Item = (usize, &InstructionContext)> {
+        self.instruction_trace
+            .iter()
+            .take(self.get_instruction_trace_length())
+            .map(|instruction_context| (instruction_context.nesting_level, instruction_context))
+    }
+
+    /// Renders the InstructionContext trace as folded stacks, e.g. for flamegraph tooling
+    ///
+    /// Each line is the call path of one leaf instruction, in execution order, as the
+    /// semicolon-separated program keys from the top level instruction down to the leaf.
+    #[cfg(not(target_os = "solana"))]
+    pub fn folded_stacks(&self) -> Vec<String> {
+        let mut folded_stacks = Vec::new();
+        let mut call_path = Vec::<String>::new();
+        let mut trace = self.trace_with_depth().peekable();
+        while let Some((nesting_level, instruction_context)) = trace.next() {
+            call_path.truncate(nesting_level);
+            call_path.push(
+                instruction_context
+                    .get_last_program_key(self)
+                    .map(ToString::to_string)
+                    .unwrap_or_default(),
+            );
+            let has_callee = matches!(
+                trace.peek(),
+                Some((next_nesting_level, _)) if *next_nesting_level > nesting_level
+            );
+            if !has_callee {
+                folded_stacks.push(call_path.join(";"));
+            }
+        }
+        folded_stacks
+    }
+
+    /// Finds instructions in the trace which invoked the same program with the same data
+    ///
+    /// Returns pairs of `(index_in_trace_of_first_occurrence, index_in_trace_of_duplicate)`.
+    pub fn find_duplicate_instructions(&self) -> Vec<(usize, usize)> {
+        let mut first_occurrences = HashMap::<(&Pubkey, &[u8]), usize>::new();
+        let mut duplicates = Vec::new();
+        for (index_in_trace, instruction_context) in self
+            .instruction_trace
+            .iter()
+            .take(self.get_instruction_trace_length())
+            .enumerate()
+        {
+            let Ok(program_key) = instruction_context.get_last_program_key(self) else {
+                continue;
+            };
+            let first_index_in_trace = *first_occurrences
+                .entry((program_key, instruction_context.get_instruction_data()))
+                .or_insert(index_in_trace);
+            if first_index_in_trace != index_in_trace {
+                duplicates.push((first_index_in_trace, index_in_trace));
+            }
+        }
+        duplicates
+    }
+
+    /// Gets the max height of the InstructionContext stack
     pub fn get_instruction_stack_capacity(&self) -> usize {
         self.instruction_stack_capacity
     }
@@ -351,6 +1143,45 @@ impl TransactionContext {
         self.instruction_stack.len()
     }
 
+    /// Returns the instruction stack, ordered by nesting level
+    ///
+    /// Each entry is an index into the trace, see `get_instruction_context_at_index_in_trace()`.
+    pub fn instruction_stack(&self) -> &[usize] {
+        &self.instruction_stack
+    }
+
+    /// Returns true while the first top-level instruction is executing, but not its CPIs
+    pub fn is_first_top_level_instruction(&self) -> bool {
+        self.top_level_instruction_index == 0
+            && self.get_instruction_context_stack_height()
+                == solana_instruction::TRANSACTION_LEVEL_STACK_HEIGHT
+    }
+
+    /// Returns how many more InstructionContexts can be pushed onto the stack
+    pub fn remaining_stack_depth(&self) -> usize {
+        self.instruction_stack_capacity
+            .saturating_sub(self.get_instruction_context_stack_height())
+    }
+
+    /// Returns how many more InstructionContexts can be recorded in the trace
+    pub fn remaining_trace_capacity(&self) -> usize {
+        self.instruction_trace_capacity
+            .saturating_sub(self.get_instruction_trace_length())
+    }
+
+    /// Returns an error if the next InstructionContext could not be pushed
+    ///
+    /// These are the same limits `push()` enforces, checked in the same order.
+    pub fn can_accept_next_instruction(&self) -> Result<(), InstructionError> {
+        if self.remaining_trace_capacity() == 0 {
+            return Err(InstructionError::MaxInstructionTraceLengthExceeded);
+        }
+        if self.remaining_stack_depth() == 0 {
+            return Err(InstructionError::CallDepth);
+        }
+        Ok(())
+    }
+
     /// Returns the current InstructionContext
     pub fn get_current_instruction_context(&self) -> Result<&InstructionContext, InstructionError> {
         let level = self
@@ -360,6 +1191,43 @@ impl TransactionContext {
         self.get_instruction_context_at_nesting_level(level)
     }
 
+    /// Returns the index in the transaction of the currently executing program
+    pub fn current_program_index(&self) -> Result<IndexOfAccount, InstructionError> {
+        let instruction_context = self.get_current_instruction_context()?;
+        instruction_context.get_index_of_program_account_in_transaction(
+            instruction_context
+                .get_number_of_program_accounts()
+                .saturating_sub(1),
+        )
+    }
+
+    /// Returns the InstructionContext which invoked the current one, or `None` at the top level
+    ///
+    /// Fails with `CallDepth` if no instruction is being executed.
+    pub fn get_parent_instruction_context(
+        &self,
+    ) -> Result<Option<&InstructionContext>, InstructionError> {
+        let level = self
+            .get_instruction_context_stack_height()
+            .checked_sub(1)
+            .ok_or(InstructionError::CallDepth)?;
+        let Some(parent_level) = level.checked_sub(1) else {
+            return Ok(None);
+        };
+        self.get_instruction_context_at_nesting_level(parent_level)
+            .map(Some)
+    }
+
+    /// Returns the program key of the parent InstructionContext, or `None` at the top level
+    pub fn get_caller_program_key(&self) -> Result<Option<&Pubkey>, InstructionError> {
+        let Some(caller_level) = self.get_instruction_context_stack_height().checked_sub(2) else {
+            return Ok(None);
+        };
+        self.get_instruction_context_at_nesting_level(caller_level)?
+            .get_last_program_key(self)
+            .map(Some)
+    }
+
     /// Returns the mutable InstructionContext to configure for the next invocation.
     ///
     /// The last InstructionContext is always empty and pre-reserved for the next instruction.
@@ -417,6 +1285,12 @@ impl TransactionContext {
             return Err(InstructionError::CallDepth);
         }
         self.instruction_stack.push(index_in_trace);
+        if let Some(observer) = &self.stack_observer {
+            (observer.0)(StackEvent::Push {
+                nesting_level,
+                program_key: self.last_program_key_at_index_in_trace(index_in_trace),
+            });
+        }
         if let Some(index_in_transaction) = self.find_index_of_account(&instructions::id()) {
             let mut mut_account_ref = self
                 .accounts
@@ -460,7 +1334,24 @@ impl TransactionContext {
                         })
                 });
         // Always pop, even if we `detected_an_unbalanced_instruction`
-        self.instruction_stack.pop();
+        let popped_index_in_trace = self.instruction_stack.pop();
+        if let (Some(observer), Some(index_in_trace)) =
+            (&self.stack_observer, popped_index_in_trace)
+        {
+            (observer.0)(StackEvent::Pop {
+                nesting_level: self.instruction_stack.len(),
+                program_key: self.last_program_key_at_index_in_trace(index_in_trace),
+            });
+        }
+        if let (Some(sink), Some(instruction_context)) = (
+            &self.trace_sink,
+            popped_index_in_trace
+                .and_then(|index_in_trace| self.instruction_trace.get(index_in_trace)),
+        ) {
+            if let Ok(mut sink) = sink.0.try_borrow_mut() {
+                sink(instruction_context);
+            }
+        }
         if self.instruction_stack.is_empty() {
             self.top_level_instruction_index = self.top_level_instruction_index.saturating_add(1);
         }
@@ -471,21 +1362,123 @@ impl TransactionContext {
         }
     }
 
+    #[cfg(not(target_os = "solana"))]
+    fn last_program_key_at_index_in_trace(&self, index_in_trace: usize) -> Option<Pubkey> {
+        self.instruction_trace
+            .get(index_in_trace)?
+            .get_last_program_key(self)
+            .ok()
+            .copied()
+    }
+
     /// Gets the return data of the current InstructionContext or any above
+    ///
+    /// Empty data means there is no return data, see `TransactionReturnData::is_empty()`.
     pub fn get_return_data(&self) -> (&Pubkey, &[u8]) {
         (&self.return_data.program_id, &self.return_data.data)
     }
 
+    /// Copies as much of the return data as fits into `buf`
+    ///
+    /// Returns the number of bytes copied and the program_id which set the return data.
+    pub fn read_return_data_into(&self, buf: &mut [u8]) -> (usize, &Pubkey) {
+        let length = buf.len().min(self.return_data.data.len());
+        if let (Some(destination), Some(source)) =
+            (buf.get_mut(..length), self.return_data.data.get(..length))
+        {
+            destination.copy_from_slice(source);
+        }
+        (length, &self.return_data.program_id)
+    }
+
+    /// Returns the index of the account of the program which set the return data
+    ///
+    /// Returns `None` if there is no return data or the program is not loaded.
+    pub fn return_data_program_index(&self) -> Option<IndexOfAccount> {
+        if self.return_data.is_empty() {
+            return None;
+        }
+        self.find_index_of_account(&self.return_data.program_id)
+    }
+
+    /// Returns the length of the return data
+    pub fn get_return_data_len(&self) -> usize {
+        self.return_data.data.len()
+    }
+
     /// Set the return data of the current InstructionContext
+    ///
+    /// Fails with `InvalidInstructionData` if `data` is longer than `MAX_RETURN_DATA`.
     pub fn set_return_data(
         &mut self,
         program_id: Pubkey,
         data: Vec<u8>,
     ) -> Result<(), InstructionError> {
+        if data.len() > MAX_RETURN_DATA {
+            return Err(InstructionError::InvalidInstructionData);
+        }
         self.return_data = TransactionReturnData { program_id, data };
         Ok(())
     }
 
+    /// Returns the program_id which set the return data
+    pub fn get_return_data_program_id(&self) -> &Pubkey {
+        &self.return_data.program_id
+    }
+
+    /// Resets the return data and its program_id to their defaults
+    ///
+    /// `pop()` never clears the return data, so that a caller can read what its callee set.
+    /// Call this explicitly if an instruction has to start with empty return data.
+    pub fn clear_return_data(&mut self) {
+        self.return_data = TransactionReturnData::default();
+    }
+
+    /// Calculates the sum of all lamports of all accounts in the transaction
+    ///
+    /// Capture this before execution to later pass it to `check_no_lamport_creation()`.
+    #[cfg(not(target_os = "solana"))]
+    pub fn total_lamports(&self) -> Result<u128, InstructionError> {
+        (0..self.get_number_of_accounts()).try_fold(
+            0u128,
+            |total_lamports, index_in_transaction| {
+                total_lamports
+                    .checked_add(self.accounts.try_borrow(index_in_transaction)?.lamports() as u128)
+                    .ok_or(InstructionError::ArithmeticOverflow)
+            },
+        )
+    }
+
+    /// Fails with `UnbalancedInstruction` if the accounts now hold more lamports in total than
+    /// `initial_total`, as returned by `total_lamports()` before execution
+    ///
+    /// Unlike the per instruction check in `pop()` this covers the entire transaction.
+    #[cfg(not(target_os = "solana"))]
+    pub fn check_no_lamport_creation(&self, initial_total: u128) -> Result<(), InstructionError> {
+        if self.total_lamports()? > initial_total {
+            return Err(InstructionError::UnbalancedInstruction);
+        }
+        Ok(())
+    }
+
+    /// Calculates the current sum of all lamports of the instruction accounts of the current
+    /// InstructionContext
+    ///
+    /// `pop()` fails with `UnbalancedInstruction` unless this equals
+    /// `expected_instruction_lamport_sum()`, so builtins can compare the two to fail early.
+    #[cfg(not(target_os = "solana"))]
+    pub fn current_instruction_lamport_sum(&self) -> Result<u128, InstructionError> {
+        self.instruction_accounts_lamport_sum(self.get_current_instruction_context()?)
+    }
+
+    /// Returns the sum of all lamports of the instruction accounts of the current
+    /// InstructionContext, as recorded when it was pushed
+    pub fn expected_instruction_lamport_sum(&self) -> Result<u128, InstructionError> {
+        Ok(self
+            .get_current_instruction_context()?
+            .recorded_lamport_sum())
+    }
+
     /// Calculates the sum of all lamports within an instruction
     #[cfg(not(target_os = "solana"))]
     fn instruction_accounts_lamport_sum(
@@ -525,13 +1518,393 @@ impl TransactionContext {
             .map(|value_ref| *value_ref)
     }
 
-    /// Returns a new account data write access handler
-    pub fn access_violation_handler(&self) -> AccessViolationHandler {
-        let accounts = Rc::clone(&self.accounts);
-        Box::new(
-            move |region: &mut MemoryRegion,
-                  address_space_reserved_for_account: u64,
-                  access_type: AccessType,
+    /// Returns by how many more bytes accounts may grow in total in this Transaction
+    pub fn remaining_resize_budget(&self) -> Result<i64, InstructionError> {
+        self.accounts.remaining_resize_budget()
+    }
+
+    /// Checks whether all the given accounts could be resized to the given new lengths
+    ///
+    /// The resizes are simulated in order against the per-account and per-transaction limits,
+    /// without changing any account or the accounts resize delta. Returns the first violation.
+    #[cfg(not(target_os = "solana"))]
+    pub fn can_resize_accounts(
+        &self,
+        new_lengths: &[(IndexOfAccount, usize)],
+    ) -> Result<(), InstructionError> {
+        let mut resize_delta = self.accounts_resize_delta()?;
+        let mut simulated_lengths = HashMap::<IndexOfAccount, usize>::new();
+        for (index_in_transaction, new_len) in new_lengths.iter() {
+            let old_len = match simulated_lengths.get(index_in_transaction) {
+                Some(simulated_len) => *simulated_len,
+                None => self
+                    .accounts
+                    .try_borrow(*index_in_transaction)?
+                    .data()
+                    .len(),
+            };
+            self.accounts.can_data_be_resized_with_delta(
+                resize_delta,
+                *index_in_transaction,
+                old_len,
+                *new_len,
+            )?;
+            resize_delta =
+                resize_delta.saturating_add((*new_len as i64).saturating_sub(old_len as i64));
+            simulated_lengths.insert(*index_in_transaction, *new_len);
+        }
+        Ok(())
+    }
+
+    /// Counts the accounts whose data length differs from the one at the start of the Transaction
+    ///
+    /// See `account_size_delta()` for the change of an individual account.
+    pub fn resized_account_count(&self) -> Result<usize, InstructionError> {
+        let mut resized_account_count = 0usize;
+        for (index_in_transaction, original_data_len) in
+            self.accounts.original_data_lengths.iter().enumerate()
+        {
+            if self
+                .accounts
+                .try_borrow(index_in_transaction as IndexOfAccount)?
+                .data()
+                .len()
+                != *original_data_len
+            {
+                resized_account_count = resized_account_count.saturating_add(1);
+            }
+        }
+        Ok(resized_account_count)
+    }
+
+    /// Returns the highest total data length of all accounts reached so far in this Transaction
+    ///
+    /// Unlike `accounts_resize_delta()` this also captures accounts which grew and shrunk again.
+    pub fn peak_total_data_len(&self) -> usize {
+        let original_total_data_len = self
+            .accounts
+            .original_data_lengths
+            .iter()
+            .fold(0usize, |accumulator, data_len| {
+                accumulator.saturating_add(*data_len)
+            });
+        let peak_resize_delta = self.accounts.peak_resize_delta.get();
+        if peak_resize_delta.is_negative() {
+            original_total_data_len
+        } else {
+            original_total_data_len.saturating_add(peak_resize_delta as usize)
+        }
+    }
+
+    /// Returns by how many bytes the data of an account grew (or shrunk) in this Transaction
+    pub fn account_size_delta(
+        &self,
+        index_in_transaction: IndexOfAccount,
+    ) -> Result<i64, InstructionError> {
+        let original_len = *self
+            .accounts
+            .original_data_lengths
+            .get(index_in_transaction as usize)
+            .ok_or(InstructionError::NotEnoughAccountKeys)?;
+        let current_len = self.accounts.try_borrow(index_in_transaction)?.data().len();
+        Ok((current_len as i64).saturating_sub(original_len as i64))
+    }
+
+    /// Returns whether an account has been touched (written to) so far in this Transaction
+    #[cfg(not(target_os = "solana"))]
+    pub fn is_account_touched(
+        &self,
+        index_in_transaction: IndexOfAccount,
+    ) -> Result<bool, InstructionError> {
+        self.accounts.is_touched(index_in_transaction)
+    }
+
+    /// Returns how many distinct accounts have been touched so far in this Transaction
+    #[cfg(not(target_os = "solana"))]
+    pub fn live_touched_count(&self) -> Result<u64, InstructionError> {
+        Ok(self
+            .accounts
+            .touched_flags
+            .try_borrow()
+            .map_err(|_| InstructionError::GenericError)?
+            .iter()
+            .fold(0u64, |accumulator, was_touched| {
+                accumulator.saturating_add(*was_touched as u64)
+            }))
+    }
+
+    /// Same as `ExecutionRecord::from()` but clones the current state instead of consuming
+    ///
+    /// This can be called at any time, even while instructions are still on the stack, in which
+    /// case the record includes their changes so far. Fails with `AccountBorrowFailed` if any
+    /// account is currently borrowed mutably.
+    #[cfg(not(target_os = "solana"))]
+    pub fn snapshot_execution_record(&self) -> Result<ExecutionRecord, InstructionError> {
+        Ok(ExecutionRecord {
+            accounts: self.clone_transaction_accounts()?,
+            return_data: self.return_data.clone(),
+            touched_account_count: self.live_touched_count()?,
+            accounts_resize_delta: self.accounts_resize_delta()?,
+        })
+    }
+
+    /// Compares the keys and current states of all accounts with those of another context
+    pub fn accounts_equal(&self, other: &TransactionContext) -> Result<bool, InstructionError> {
+        if self.account_keys != other.account_keys {
+            return Ok(false);
+        }
+        for index_in_transaction in 0..self.get_number_of_accounts() {
+            if !solana_account::accounts_equal(
+                &*self.accounts.try_borrow(index_in_transaction)?,
+                &*other.accounts.try_borrow(index_in_transaction)?,
+            ) {
+                return Ok(false);
+            }
+        }
+        Ok(true)
+    }
+
+    /// Returns whether any account is owned by the native loader
+    #[cfg(not(target_os = "solana"))]
+    pub fn has_native_program_accounts(&self) -> Result<bool, InstructionError> {
+        for index_in_transaction in 0..self.get_number_of_accounts() {
+            if solana_sdk_ids::native_loader::check_id(
+                self.accounts.try_borrow(index_in_transaction)?.owner(),
+            ) {
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    }
+
+    /// Returns the indices of all accounts owned by the native loader
+    #[cfg(not(target_os = "solana"))]
+    pub fn native_program_account_indices(&self) -> Result<Vec<IndexOfAccount>, InstructionError> {
+        let mut native_program_account_indices = Vec::new();
+        for index_in_transaction in 0..self.get_number_of_accounts() {
+            if solana_sdk_ids::native_loader::check_id(
+                self.accounts.try_borrow(index_in_transaction)?.owner(),
+            ) {
+                native_program_account_indices.push(index_in_transaction);
+            }
+        }
+        Ok(native_program_account_indices)
+    }
+
+    /// Returns how many lamports all accounts together need to hold to be rent exempt
+    #[cfg(not(target_os = "solana"))]
+    pub fn total_rent_exempt_minimum(&self) -> Result<u64, InstructionError> {
+        let mut total_rent_exempt_minimum = 0u64;
+        for index_in_transaction in 0..self.get_number_of_accounts() {
+            let data_len = self.accounts.try_borrow(index_in_transaction)?.data().len();
+            total_rent_exempt_minimum =
+                total_rent_exempt_minimum.saturating_add(self.rent.minimum_balance(data_len));
+        }
+        Ok(total_rent_exempt_minimum)
+    }
+
+    /// Reports for every account whether it is rent exempt and how many lamports it is short
+    ///
+    /// Uses the current lamports and data length of each account. Accounts without any lamports
+    /// are not special cased, they are reported with the full minimum balance as their deficit.
+    #[cfg(not(target_os = "solana"))]
+    pub fn rent_collection_summary(&self) -> Result<RentSummary, InstructionError> {
+        let mut summary = RentSummary {
+            accounts: Vec::with_capacity(self.get_number_of_accounts() as usize),
+            total_deficit: 0,
+        };
+        for index_in_transaction in 0..self.get_number_of_accounts() {
+            let account = self.accounts.try_borrow(index_in_transaction)?;
+            let deficit = self
+                .rent
+                .minimum_balance(account.data().len())
+                .saturating_sub(account.lamports());
+            summary.accounts.push(AccountRentState {
+                is_rent_exempt: deficit == 0,
+                deficit,
+            });
+            summary.total_deficit = summary.total_deficit.saturating_add(deficit);
+        }
+        Ok(summary)
+    }
+
+    /// Returns the indices of all touched accounts which are now owned by the system program
+    /// and hold no lamports, i.e. which were closed in this Transaction
+    #[cfg(not(target_os = "solana"))]
+    pub fn accounts_closed(&self) -> Result<Vec<IndexOfAccount>, InstructionError> {
+        let mut accounts_closed = Vec::new();
+        for index_in_transaction in 0..self.get_number_of_accounts() {
+            if !self.accounts.is_touched(index_in_transaction)? {
+                continue;
+            }
+            let account = self.accounts.try_borrow(index_in_transaction)?;
+            if account.lamports() == 0 && solana_sdk_ids::system_program::check_id(account.owner())
+            {
+                accounts_closed.push(index_in_transaction);
+            }
+        }
+        Ok(accounts_closed)
+    }
+
+    /// Returns the indices of all accounts owned by the given program
+    pub fn find_accounts_by_owner(
+        &self,
+        owner: &Pubkey,
+    ) -> Result<Vec<IndexOfAccount>, InstructionError> {
+        let mut accounts_by_owner = Vec::new();
+        for index_in_transaction in 0..self.get_number_of_accounts() {
+            if self.accounts.try_borrow(index_in_transaction)?.owner() == owner {
+                accounts_by_owner.push(index_in_transaction);
+            }
+        }
+        Ok(accounts_by_owner)
+    }
+
+    /// Returns the data length of every account, in the order of their index in the transaction
+    pub fn account_data_lengths(&self) -> Result<Vec<usize>, InstructionError> {
+        (0..self.get_number_of_accounts())
+            .map(|index_in_transaction| {
+                self.accounts
+                    .try_borrow(index_in_transaction)
+                    .map(|account| account.data().len())
+            })
+            .collect()
+    }
+
+    /// Reports the accounts which differ from those of another context
+    ///
+    /// Accounts are compared pairwise by their index in the transaction. Fails with
+    /// `NotEnoughAccountKeys` if the contexts have a different number of accounts and with
+    /// `AccountBorrowFailed` if any account is mutably borrowed.
+    #[cfg(all(
+        not(target_os = "solana"),
+        any(test, feature = "dev-context-only-utils")
+    ))]
+    pub fn diff(&self, other: &TransactionContext) -> Result<Vec<AccountDiff>, InstructionError> {
+        if self.get_number_of_accounts() != other.get_number_of_accounts() {
+            return Err(InstructionError::NotEnoughAccountKeys);
+        }
+        let mut account_diffs = Vec::new();
+        for (index_in_transaction, (key, other_key)) in self
+            .account_keys
+            .iter()
+            .zip(other.account_keys.iter())
+            .enumerate()
+        {
+            let index_in_transaction = index_in_transaction as IndexOfAccount;
+            let account = self.accounts.try_borrow(index_in_transaction)?;
+            let other_account = other.accounts.try_borrow(index_in_transaction)?;
+            let account_diff = AccountDiff {
+                index_in_transaction,
+                key: *key,
+                other_key: (key != other_key).then_some(*other_key),
+                lamports: account.lamports() != other_account.lamports(),
+                owner: account.owner() != other_account.owner(),
+                data: account.data() != other_account.data(),
+                executable: account.executable() != other_account.executable(),
+                rent_epoch: account.rent_epoch() != other_account.rent_epoch(),
+            };
+            if account_diff.other_key.is_some()
+                || account_diff.lamports
+                || account_diff.owner
+                || account_diff.data
+                || account_diff.executable
+                || account_diff.rent_epoch
+            {
+                account_diffs.push(account_diff);
+            }
+        }
+        Ok(account_diffs)
+    }
+
+    /// Returns the indices of all accounts which were rent exempt before this Transaction and
+    /// are no longer rent exempt now
+    ///
+    /// Accounts which were not rent exempt to begin with are not included. The original lamports
+    /// are only known for changes made via `BorrowedAccount::set_lamports()`, not for accounts
+    /// modified through `get_account_at_index()` directly.
+    #[cfg(not(target_os = "solana"))]
+    pub fn accounts_needing_rent_update(&self) -> Result<Vec<IndexOfAccount>, InstructionError> {
+        let mut accounts_needing_rent_update = Vec::new();
+        for (index_in_transaction, original_data_len) in
+            self.accounts.original_data_lengths.iter().enumerate()
+        {
+            let index_in_transaction = index_in_transaction as IndexOfAccount;
+            let account = self.accounts.try_borrow(index_in_transaction)?;
+            let original_lamports = self
+                .accounts
+                .original_lamports(index_in_transaction)?
+                .unwrap_or_else(|| account.lamports());
+            if self.rent.is_exempt(original_lamports, *original_data_len)
+                && !self
+                    .rent
+                    .is_exempt(account.lamports(), account.data().len())
+            {
+                accounts_needing_rent_update.push(index_in_transaction);
+            }
+        }
+        Ok(accounts_needing_rent_update)
+    }
+
+    /// Counts how many accounts are still shared and how many have been made unique
+    ///
+    /// Returns `(shared_count, unique_count)`, see `BorrowedAccount::is_shared()`.
+    #[cfg(not(target_os = "solana"))]
+    pub fn count_shared_accounts(&self) -> Result<(usize, usize), InstructionError> {
+        let mut shared_count = 0usize;
+        for index_in_transaction in 0..self.get_number_of_accounts() {
+            if self.accounts.try_borrow(index_in_transaction)?.is_shared() {
+                shared_count = shared_count.saturating_add(1);
+            }
+        }
+        Ok((
+            shared_count,
+            self.accounts.len().saturating_sub(shared_count),
+        ))
+    }
+
+    /// Summarizes all accounts for logging, without panicking on outstanding borrows
+    pub fn try_dump_accounts(&self) -> Result<Vec<(Pubkey, AccountSummary)>, InstructionError> {
+        self.account_keys
+            .iter()
+            .zip(self.accounts.accounts.iter())
+            .map(|(key, account)| {
+                let account = account
+                    .try_borrow()
+                    .map_err(|_| InstructionError::AccountBorrowOutstanding)?;
+                Ok((
+                    *key,
+                    AccountSummary {
+                        lamports: account.lamports(),
+                        owner: *account.owner(),
+                        data_len: account.data().len(),
+                    },
+                ))
+            })
+            .collect()
+    }
+
+    /// Limits how far the data of an account can grow
+    ///
+    /// Growing the account beyond `max_data_length` fails with `InvalidRealloc`.
+    /// Accounts without a configured limit can grow up to `MAX_PERMITTED_DATA_LENGTH`.
+    #[cfg(not(target_os = "solana"))]
+    pub fn set_account_max_length(
+        &self,
+        index_in_transaction: IndexOfAccount,
+        max_data_length: usize,
+    ) -> Result<(), InstructionError> {
+        self.accounts
+            .set_max_data_length(index_in_transaction, max_data_length)
+    }
+
+    /// Returns a new account data write access handler
+    pub fn access_violation_handler(&self) -> AccessViolationHandler {
+        let accounts = Rc::clone(&self.accounts);
+        Box::new(
+            move |region: &mut MemoryRegion,
+                  address_space_reserved_for_account: u64,
+                  access_type: AccessType,
                   vm_addr: u64,
                   len: u64| {
                 if access_type == AccessType::Load {
@@ -563,12 +1936,9 @@ impl TransactionContext {
                     debug_assert!(false);
                     return;
                 }
-                let Ok(remaining_allowed_growth) =
-                    accounts.resize_delta.try_borrow().map(|resize_delta| {
-                        MAX_PERMITTED_ACCOUNTS_DATA_ALLOCATIONS_PER_TRANSACTION
-                            .saturating_sub(*resize_delta)
-                            .max(0) as usize
-                    })
+                let Ok(remaining_allowed_growth) = accounts
+                    .remaining_resize_budget()
+                    .map(|remaining_resize_budget| remaining_resize_budget as usize)
                 else {
                     debug_assert!(false);
                     return;
@@ -578,12 +1948,19 @@ impl TransactionContext {
                     // Realloc immediately here to fit the requested access,
                     // then later in CPI or deserialization realloc again to the
                     // account length the program stored in AccountInfo.
+                    let Ok(max_data_length) = accounts.max_data_length(index_in_transaction) else {
+                        debug_assert!(false);
+                        return;
+                    };
                     let old_len = account.data().len();
                     let new_len = (address_space_reserved_for_account as usize)
                         .min(MAX_PERMITTED_DATA_LENGTH as usize)
+                        .min(max_data_length.max(old_len))
                         .min(old_len.saturating_add(remaining_allowed_growth));
-                    // The last two min operations ensure the following:
-                    debug_assert!(accounts.can_data_be_resized(old_len, new_len).is_ok());
+                    // The last three min operations ensure the following:
+                    debug_assert!(accounts
+                        .can_data_be_resized(index_in_transaction, old_len, new_len)
+                        .is_ok());
                     if accounts
                         .update_accounts_resize_delta(old_len, new_len)
                         .is_err()
@@ -602,6 +1979,52 @@ impl TransactionContext {
     }
 }
 
+/// Passed to the observer registered via `TransactionContext::set_stack_observer()`
+#[cfg(not(target_os = "solana"))]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum StackEvent {
+    /// An InstructionContext was pushed onto the stack
+    Push {
+        nesting_level: usize,
+        program_key: Option<Pubkey>,
+    },
+    /// An InstructionContext was popped from the stack
+    Pop {
+        nesting_level: usize,
+        program_key: Option<Pubkey>,
+    },
+}
+
+#[cfg(not(target_os = "solana"))]
+type TraceSink = RefCell<Box<dyn FnMut(&InstructionContext)>>;
+
+/// Holds a callback, so that the structs storing it can keep deriving their traits.
+///
+/// Callbacks are purely observational and thus never make two contexts unequal.
+#[cfg(not(target_os = "solana"))]
+struct Observer<T: ?Sized>(Rc<T>);
+
+#[cfg(not(target_os = "solana"))]
+impl<T: ?Sized> Clone for Observer<T> {
+    fn clone(&self) -> Self {
+        Self(Rc::clone(&self.0))
+    }
+}
+
+#[cfg(not(target_os = "solana"))]
+impl<T: ?Sized> std::fmt::Debug for Observer<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("Observer")
+    }
+}
+
+#[cfg(not(target_os = "solana"))]
+impl<T: ?Sized> PartialEq for Observer<T> {
+    fn eq(&self, _other: &Self) -> bool {
+        true
+    }
+}
+
 /// Return data at the end of a transaction
 #[cfg_attr(
     feature = "serde",
@@ -613,9 +2036,78 @@ pub struct TransactionReturnData {
     pub data: Vec<u8>,
 }
 
+impl TransactionReturnData {
+    /// Returns true if there is no return data, regardless of the program_id
+    pub fn is_empty(&self) -> bool {
+        self.data.is_empty()
+    }
+}
+
+/// Lightweight summary of an account, see `TransactionContext::try_dump_accounts()`
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct AccountSummary {
+    pub lamports: u64,
+    pub owner: Pubkey,
+    pub data_len: usize,
+}
+
+/// How an account was accessed, see `TransactionContext::account_access_kind()`
+#[cfg(all(
+    not(target_os = "solana"),
+    any(test, feature = "dev-context-only-utils")
+))]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AccessKind {
+    None,
+    ReadOnly,
+    WriteOnly,
+    ReadWrite,
+}
+
+/// Rent state of all accounts, see `TransactionContext::rent_collection_summary()`
+#[cfg(not(target_os = "solana"))]
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct RentSummary {
+    /// In the order of their index in the transaction
+    pub accounts: Vec<AccountRentState>,
+    /// Sum of the deficits of all accounts
+    pub total_deficit: u64,
+}
+
+/// Rent state of one account, see `TransactionContext::rent_collection_summary()`
+#[cfg(not(target_os = "solana"))]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct AccountRentState {
+    pub is_rent_exempt: bool,
+    /// Lamports missing to reach the rent exempt minimum balance, zero if rent exempt
+    pub deficit: u64,
+}
+
+/// Differences of one account between two contexts, see `TransactionContext::diff()`
+#[cfg(all(
+    not(target_os = "solana"),
+    any(test, feature = "dev-context-only-utils")
+))]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct AccountDiff {
+    pub index_in_transaction: IndexOfAccount,
+    pub key: Pubkey,
+    /// The key in the other context, if it is not the same
+    pub other_key: Option<Pubkey>,
+    pub lamports: bool,
+    pub owner: bool,
+    pub data: bool,
+    pub executable: bool,
+    pub rent_epoch: bool,
+}
+
 /// Loaded instruction shared between runtime and programs.
 ///
 /// This context is valid for the entire duration of a (possibly cross program) instruction being processed.
+#[cfg_attr(
+    feature = "serde",
+    derive(serde_derive::Deserialize, serde_derive::Serialize)
+)]
 #[derive(Debug, Clone, Default, Eq, PartialEq)]
 pub struct InstructionContext {
     nesting_level: usize,
@@ -626,6 +2118,26 @@ pub struct InstructionContext {
 }
 
 impl InstructionContext {
+    /// Constructs an InstructionContext directly, without pushing it onto a TransactionContext
+    #[cfg(all(
+        not(target_os = "solana"),
+        any(test, feature = "dev-context-only-utils")
+    ))]
+    pub fn new_for_tests(
+        nesting_level: usize,
+        program_accounts: Vec<IndexOfAccount>,
+        instruction_accounts: Vec<InstructionAccount>,
+        instruction_data: Vec<u8>,
+    ) -> Self {
+        Self {
+            nesting_level,
+            instruction_accounts_lamport_sum: 0,
+            program_accounts,
+            instruction_accounts,
+            instruction_data,
+        }
+    }
+
     /// Used together with TransactionContext::get_next_instruction_context()
     #[cfg(not(target_os = "solana"))]
     pub fn configure(
@@ -639,6 +2151,32 @@ impl InstructionContext {
         self.instruction_data = instruction_data.to_vec();
     }
 
+    /// Same as `configure()` but rejects data longer than `MAX_INSTRUCTION_DATA_LEN`
+    #[cfg(not(target_os = "solana"))]
+    pub fn try_configure(
+        &mut self,
+        program_accounts: Vec<IndexOfAccount>,
+        instruction_accounts: Vec<InstructionAccount>,
+        instruction_data: &[u8],
+    ) -> Result<(), InstructionError> {
+        if instruction_data.len() > MAX_INSTRUCTION_DATA_LEN {
+            return Err(InstructionError::InvalidInstructionData);
+        }
+        self.configure(program_accounts, instruction_accounts, instruction_data);
+        Ok(())
+    }
+
+    /// Overwrites the data parameter of this Instruction.
+    ///
+    /// This must happen after `configure()` but before the Instruction is pushed and executed.
+    #[cfg(all(
+        not(target_os = "solana"),
+        any(test, feature = "dev-context-only-utils")
+    ))]
+    pub fn set_instruction_data(&mut self, data: Vec<u8>) {
+        self.instruction_data = data;
+    }
+
     /// How many Instructions were on the stack after this one was pushed
     ///
     /// That is the number of nested parent Instructions plus one (itself).
@@ -646,6 +2184,32 @@ impl InstructionContext {
         self.nesting_level.saturating_add(1)
     }
 
+    /// Sum of all lamports of the instruction accounts, as recorded when this Instruction was pushed
+    pub fn recorded_lamport_sum(&self) -> u128 {
+        self.instruction_accounts_lamport_sum
+    }
+
+    /// Checks that all program and instruction accounts point into the given TransactionContext
+    pub fn validate_against(
+        &self,
+        transaction_context: &TransactionContext,
+    ) -> Result<(), InstructionError> {
+        let number_of_accounts = transaction_context.get_number_of_accounts();
+        if self
+            .program_accounts
+            .iter()
+            .chain(
+                self.instruction_accounts
+                    .iter()
+                    .map(|instruction_account| &instruction_account.index_in_transaction),
+            )
+            .any(|index_in_transaction| *index_in_transaction >= number_of_accounts)
+        {
+            return Err(InstructionError::NotEnoughAccountKeys);
+        }
+        Ok(())
+    }
+
     /// Number of program accounts
     pub fn get_number_of_program_accounts(&self) -> IndexOfAccount {
         self.program_accounts.len() as IndexOfAccount
@@ -668,11 +2232,59 @@ impl InstructionContext {
         }
     }
 
+    /// Assert that no more than `max` accounts were supplied to this Instruction
+    ///
+    /// Fails with `MaxAccountsExceeded` otherwise.
+    pub fn check_max_number_of_instruction_accounts(
+        &self,
+        max: IndexOfAccount,
+    ) -> Result<(), InstructionError> {
+        if self.get_number_of_instruction_accounts() > max {
+            Err(InstructionError::MaxAccountsExceeded)
+        } else {
+            Ok(())
+        }
+    }
+
     /// Data parameter for the programs `process_instruction` handler
     pub fn get_instruction_data(&self) -> &[u8] {
         &self.instruction_data
     }
 
+    /// Returns the data parameter of this Instruction hex-encoded, for logging
+    #[cfg(all(
+        not(target_os = "solana"),
+        any(test, feature = "dev-context-only-utils")
+    ))]
+    pub fn instruction_data_hex(&self) -> String {
+        self.instruction_data_hex_truncated(self.instruction_data.len())
+    }
+
+    /// Same as `instruction_data_hex()` but only encodes the first `max_len` bytes
+    ///
+    /// If any bytes were omitted, "..." is appended.
+    #[cfg(all(
+        not(target_os = "solana"),
+        any(test, feature = "dev-context-only-utils")
+    ))]
+    pub fn instruction_data_hex_truncated(&self, max_len: usize) -> String {
+        use std::fmt::Write;
+        let mut hex =
+            String::with_capacity(max_len.min(self.instruction_data.len()).saturating_mul(2));
+        for byte in self.instruction_data.iter().take(max_len) {
+            let _ = write!(hex, "{byte:02x}");
+        }
+        if self.instruction_data.len() > max_len {
+            hex.push_str("...");
+        }
+        hex
+    }
+
+    /// Returns whether the data parameter of this Instruction equals the given bytes
+    pub fn instruction_data_eq(&self, other: &[u8]) -> bool {
+        self.instruction_data == other
+    }
+
     /// Searches for a program account by its key
     pub fn find_index_of_program_account(
         &self,
@@ -742,8 +2354,22 @@ impl InstructionContext {
             .ok_or(InstructionError::MissingAccount)
     }
 
-    /// Returns `Some(instruction_account_index)` if this is a duplicate
-    /// and `None` if it is the first account with this key
+    /// Iterates over the indices of all instruction accounts which point to the given account
+    ///
+    /// Unlike `get_index_of_account_in_instruction()` this includes duplicates, in ascending order.
+    pub fn indices_of_instruction_account_in_transaction(
+        &self,
+        index_in_transaction: IndexOfAccount,
+    ) -> impl Iterator<Item = IndexOfAccount> + '_ {
+        self.instruction_accounts
+            .iter()
+            .enumerate()
+            .filter(move |(_, account)| account.index_in_transaction == index_in_transaction)
+            .map(|(instruction_account_index, _)| instruction_account_index as IndexOfAccount)
+    }
+
+    /// Returns `Some(instruction_account_index)` if this is a duplicate
+    /// and `None` if it is the first account with this key
     pub fn is_instruction_account_duplicate(
         &self,
         instruction_account_index: IndexOfAccount,
@@ -760,6 +2386,19 @@ impl InstructionContext {
         })
     }
 
+    /// Gets the key of the first program account of this Instruction
+    ///
+    /// This is usually the loader of the program being executed.
+    pub fn get_first_program_key<'a, 'b: 'a>(
+        &'a self,
+        transaction_context: &'b TransactionContext,
+    ) -> Result<&'b Pubkey, InstructionError> {
+        self.get_index_of_program_account_in_transaction(0)
+            .and_then(|index_in_transaction| {
+                transaction_context.get_key_of_account_at_index(index_in_transaction)
+            })
+    }
+
     /// Gets the key of the last program account of this Instruction
     pub fn get_last_program_key<'a, 'b: 'a>(
         &'a self,
@@ -773,6 +2412,49 @@ impl InstructionContext {
         })
     }
 
+    /// Returns whether the given account is the program being executed by this Instruction
+    ///
+    /// Compares indices, so the same key loaded at another index does not count.
+    pub fn is_executing_program(
+        &self,
+        index_in_transaction: IndexOfAccount,
+    ) -> Result<bool, InstructionError> {
+        Ok(self.get_index_of_program_account_in_transaction(
+            self.get_number_of_program_accounts().saturating_sub(1),
+        )? == index_in_transaction)
+    }
+
+    /// Gets the owner of the last program account, which is the loader of the executing program
+    ///
+    /// The owner is returned by value, as it can not outlive the read-only borrow of the account.
+    pub fn get_program_owner(
+        &self,
+        transaction_context: &TransactionContext,
+    ) -> Result<Pubkey, InstructionError> {
+        let index_in_transaction = self.get_index_of_program_account_in_transaction(
+            self.get_number_of_program_accounts().saturating_sub(1),
+        )?;
+        transaction_context
+            .accounts
+            .try_borrow(index_in_transaction)
+            .map(|account| *account.owner())
+    }
+
+    /// Returns the keys of all instruction accounts in order, including duplicates
+    pub fn instruction_account_keys(
+        &self,
+        transaction_context: &TransactionContext,
+    ) -> Result<Vec<Pubkey>, InstructionError> {
+        self.instruction_accounts
+            .iter()
+            .map(|instruction_account| {
+                transaction_context
+                    .get_key_of_account_at_index(instruction_account.index_in_transaction)
+                    .copied()
+            })
+            .collect()
+    }
+
     fn try_borrow_account<'a, 'b: 'a>(
         &'a self,
         transaction_context: &'b TransactionContext,
@@ -785,6 +2467,10 @@ impl InstructionContext {
             .ok_or(InstructionError::MissingAccount)?
             .try_borrow_mut()
             .map_err(|_| InstructionError::AccountBorrowFailed)?;
+        #[cfg(any(test, feature = "dev-context-only-utils"))]
+        transaction_context
+            .accounts
+            .mark_borrowed(index_in_transaction)?;
         Ok(BorrowedAccount {
             transaction_context,
             instruction_context: self,
@@ -833,6 +2519,20 @@ impl InstructionContext {
         )
     }
 
+    /// Iterates over the instruction accounts of this Instruction, borrowing each one in turn
+    ///
+    /// Accounts are only borrowed when the iterator advances. If the same account is passed
+    /// more than once, holding on to an earlier item while advancing to its duplicate yields
+    /// `AccountBorrowFailed`, so drop each item before requesting the next one.
+    pub fn instruction_accounts_iter<'a>(
+        &'a self,
+        transaction_context: &'a TransactionContext,
+    ) -> impl Iterator<Item = Result<BorrowedAccount<'a>, InstructionError>> + 'a {
+        (0..self.get_number_of_instruction_accounts()).map(move |instruction_account_index| {
+            self.try_borrow_instruction_account(transaction_context, instruction_account_index)
+        })
+    }
+
     /// Returns whether an instruction account is a signer
     pub fn is_instruction_account_signer(
         &self,
@@ -874,9 +2574,230 @@ impl InstructionContext {
         Ok(result)
     }
 
+    /// Fails with `MissingRequiredSignature` on the first key which did not sign this Instruction
+    ///
+    /// Debug builds log the offending key.
+    pub fn require_all_signers(
+        &self,
+        transaction_context: &TransactionContext,
+        required: &[Pubkey],
+    ) -> Result<(), InstructionError> {
+        let signers = self.get_signers(transaction_context)?;
+        required.iter().try_for_each(|key| {
+            if signers.contains(key) {
+                return Ok(());
+            }
+            #[cfg(all(debug_assertions, not(target_os = "solana")))]
+            log::debug!("require_all_signers: {key} did not sign");
+            Err(InstructionError::MissingRequiredSignature)
+        })
+    }
+
+    /// Returns the index in the transaction of the `n`th signer instruction account
+    ///
+    /// Signers are counted in instruction order, duplicate instruction accounts count each time.
+    pub fn nth_signer_index(&self, n: usize) -> Result<IndexOfAccount, InstructionError> {
+        self.instruction_accounts
+            .iter()
+            .filter(|instruction_account| instruction_account.is_signer())
+            .nth(n)
+            .map(|instruction_account| instruction_account.index_in_transaction)
+            .ok_or(InstructionError::NotEnoughAccountKeys)
+    }
+
+    /// Checks the privileges of the instruction accounts against a declared schema
+    ///
+    /// `schema[i]` is the expected `(is_signer, is_writable)` of instruction account `i`,
+    /// instruction accounts beyond the schema are not checked. A missing signature fails with
+    /// `MissingRequiredSignature`, a missing write permission with `ReadonlyDataModified` and an
+    /// unexpected privilege with `InvalidArgument`. Debug builds log the offending index.
+    pub fn validate_account_privileges(
+        &self,
+        schema: &[(bool, bool)],
+    ) -> Result<(), InstructionError> {
+        if schema.len() > self.instruction_accounts.len() {
+            return Err(InstructionError::NotEnoughAccountKeys);
+        }
+        for (instruction_account_index, expected) in schema.iter().enumerate() {
+            let instruction_account = self
+                .instruction_accounts
+                .get(instruction_account_index)
+                .ok_or(InstructionError::NotEnoughAccountKeys)?;
+            let actual = (
+                instruction_account.is_signer(),
+                instruction_account.is_writable(),
+            );
+            let error = match (*expected, actual) {
+                ((true, _), (false, _)) => InstructionError::MissingRequiredSignature,
+                ((_, true), (_, false)) => InstructionError::ReadonlyDataModified,
+                (expected, actual) if expected != actual => InstructionError::InvalidArgument,
+                _ => continue,
+            };
+            #[cfg(all(debug_assertions, not(target_os = "solana")))]
+            log::debug!(
+                "validate_account_privileges: instruction account \
+                 {instruction_account_index} does not match the schema"
+            );
+            return Err(error);
+        }
+        Ok(())
+    }
+
     pub fn instruction_accounts(&self) -> &[InstructionAccount] {
         &self.instruction_accounts
     }
+
+    /// Returns the indices of all instruction accounts, ordered by their index in the transaction
+    ///
+    /// Duplicate accounts are included and keep their relative instruction order.
+    pub fn instruction_accounts_by_transaction_index(&self) -> Vec<IndexOfAccount> {
+        let mut instruction_account_indices =
+            (0..self.get_number_of_instruction_accounts()).collect::<Vec<_>>();
+        instruction_account_indices.sort_by_key(|instruction_account_index| {
+            self.instruction_accounts
+                .get(*instruction_account_index as usize)
+                .map(|instruction_account| instruction_account.index_in_transaction)
+        });
+        instruction_account_indices
+    }
+
+    /// Returns the indices of all instruction accounts which are uninitialized
+    ///
+    /// See `BorrowedAccount::is_default()`.
+    pub fn uninitialized_instruction_accounts(
+        &self,
+        transaction_context: &TransactionContext,
+    ) -> Result<Vec<IndexOfAccount>, InstructionError> {
+        let mut uninitialized_instruction_accounts = Vec::new();
+        for instruction_account_index in 0..self.get_number_of_instruction_accounts() {
+            let index_in_transaction =
+                self.get_index_of_instruction_account_in_transaction(instruction_account_index)?;
+            let account = transaction_context
+                .accounts
+                .try_borrow(index_in_transaction)?;
+            if is_default_account(&account) {
+                uninitialized_instruction_accounts.push(instruction_account_index);
+            }
+        }
+        Ok(uninitialized_instruction_accounts)
+    }
+
+    /// Returns the indices of all writable instruction accounts not owned by the executing program
+    ///
+    /// The program can credit lamports to these accounts but can not modify their data.
+    pub fn writable_but_unowned_indices(
+        &self,
+        transaction_context: &TransactionContext,
+    ) -> Result<Vec<IndexOfAccount>, InstructionError> {
+        let program_key = self.get_last_program_key(transaction_context)?;
+        let mut writable_but_unowned_indices = Vec::new();
+        for instruction_account_index in 0..self.get_number_of_instruction_accounts() {
+            if !self.is_instruction_account_writable(instruction_account_index)? {
+                continue;
+            }
+            let index_in_transaction =
+                self.get_index_of_instruction_account_in_transaction(instruction_account_index)?;
+            let account = transaction_context
+                .accounts
+                .try_borrow(index_in_transaction)?;
+            if account.owner() != program_key {
+                writable_but_unowned_indices.push(instruction_account_index);
+            }
+        }
+        Ok(writable_but_unowned_indices)
+    }
+
+    /// Exports the privileges of all instruction accounts, keyed by their index in the transaction
+    ///
+    /// Each `u8` packs the signer flag in bit 0 and the writable flag in bit 1, see
+    /// `decode_account_privileges()`. Duplicate accounts are merged by combining their flags and
+    /// the result is sorted by the index in the transaction.
+    pub fn export_account_privileges(&self) -> Vec<(IndexOfAccount, u8)> {
+        let mut privileges = self
+            .instruction_accounts
+            .iter()
+            .map(|instruction_account| {
+                let mut privileges = 0u8;
+                if instruction_account.is_signer() {
+                    privileges |= SIGNER_PRIVILEGE;
+                }
+                if instruction_account.is_writable() {
+                    privileges |= WRITABLE_PRIVILEGE;
+                }
+                (instruction_account.index_in_transaction, privileges)
+            })
+            .collect::<Vec<_>>();
+        privileges.sort_by_key(|(index_in_transaction, _)| *index_in_transaction);
+        privileges.dedup_by(|duplicate, first| {
+            if duplicate.0 == first.0 {
+                first.1 |= duplicate.1;
+                true
+            } else {
+                false
+            }
+        });
+        privileges
+    }
+
+    /// Decodes privileges from `export_account_privileges()` into `(is_signer, is_writable)`
+    pub fn decode_account_privileges(privileges: u8) -> (bool, bool) {
+        (
+            privileges & SIGNER_PRIVILEGE != 0,
+            privileges & WRITABLE_PRIVILEGE != 0,
+        )
+    }
+
+    /// Packs the signer and writable flags of all instruction accounts into bitsets
+    ///
+    /// Returns `(signers, writables)` where bit `i % 64` of word `i / 64` corresponds to the
+    /// instruction account at index `i`.
+    pub fn privilege_bitsets(&self) -> (Vec<u64>, Vec<u64>) {
+        let number_of_words = self.instruction_accounts.len().div_ceil(64);
+        let mut signers = vec![0u64; number_of_words];
+        let mut writables = vec![0u64; number_of_words];
+        for (instruction_account_index, instruction_account) in
+            self.instruction_accounts.iter().enumerate()
+        {
+            let word_index = instruction_account_index / 64;
+            let bit = 1u64 << (instruction_account_index % 64);
+            if let Some(word) = signers.get_mut(word_index) {
+                if instruction_account.is_signer() {
+                    *word |= bit;
+                }
+            }
+            if let Some(word) = writables.get_mut(word_index) {
+                if instruction_account.is_writable() {
+                    *word |= bit;
+                }
+            }
+        }
+        (signers, writables)
+    }
+
+    /// Records the lamport sum of the given instruction accounts, see `BalancedBorrowGuard`
+    #[cfg(not(target_os = "solana"))]
+    pub fn balanced_borrow_guard<'a, 'b: 'a>(
+        &'a self,
+        transaction_context: &'b TransactionContext,
+        instruction_account_indices: &[IndexOfAccount],
+    ) -> Result<BalancedBorrowGuard<'a>, InstructionError> {
+        let mut indices_in_transaction = instruction_account_indices
+            .iter()
+            .map(|instruction_account_index| {
+                self.get_index_of_instruction_account_in_transaction(*instruction_account_index)
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        indices_in_transaction.sort_unstable();
+        indices_in_transaction.dedup();
+        let lamport_sum =
+            BalancedBorrowGuard::lamport_sum(transaction_context, &indices_in_transaction)?;
+        Ok(BalancedBorrowGuard {
+            transaction_context,
+            indices_in_transaction,
+            lamport_sum,
+            committed_delta: 0,
+        })
+    }
 }
 
 /// Shared account borrowed from the TransactionContext and an InstructionContext.
@@ -916,6 +2837,15 @@ impl BorrowedAccount<'_> {
         self.account.owner()
     }
 
+    /// Returns the owner and the stored executable flag of this account (transaction wide)
+    ///
+    /// Unlike `is_executable_effective()` this ignores `remove_accounts_executable_flag_checks`,
+    /// as needed for serialization.
+    #[inline]
+    pub fn owner_and_executable(&self) -> (&Pubkey, bool) {
+        (self.account.owner(), self.account.executable())
+    }
+
     /// Assignes the owner of this account (transaction wide)
     #[cfg(not(target_os = "solana"))]
     pub fn set_owner(&mut self, pubkey: &[u8]) -> Result<(), InstructionError> {
@@ -932,7 +2862,7 @@ impl BorrowedAccount<'_> {
             return Err(InstructionError::ModifiedProgramId);
         }
         // and only if the data is zero-initialized or empty
-        if !is_zeroed(self.get_data()) {
+        if !is_zeroed(self.account.data()) {
             return Err(InstructionError::ModifiedProgramId);
         }
         // don't touch the account if the owner does not change
@@ -944,6 +2874,41 @@ impl BorrowedAccount<'_> {
         Ok(())
     }
 
+    /// Initializes a new account by resizing its data, funding it and assigning the owner
+    /// (transaction wide)
+    ///
+    /// All preconditions are validated first, so that on error the account is left unchanged.
+    /// The account must not hold any lamports or data yet and must be owned by the current
+    /// program.
+    #[cfg(not(target_os = "solana"))]
+    pub fn initialize(
+        &mut self,
+        owner: &[u8],
+        lamports: u64,
+        space: usize,
+    ) -> Result<(), InstructionError> {
+        if owner.len() != std::mem::size_of::<Pubkey>() {
+            return Err(InstructionError::InvalidArgument);
+        }
+        // Only an empty account can be initialized
+        if !self.is_default() {
+            return Err(InstructionError::AccountAlreadyInitialized);
+        }
+        // This also covers the preconditions of set_lamports() and set_owner():
+        // The account is owned by the current program, writable and not executable.
+        self.can_data_be_resized(space)?;
+        self.set_data_length(space)?;
+        self.set_lamports(lamports)?;
+        // Must come last, as the current program no longer owns the account afterwards
+        self.set_owner(owner)
+    }
+
+    /// Returns true if this account has no lamports and no data (transaction wide)
+    #[inline]
+    pub fn is_default(&self) -> bool {
+        is_default_account(&self.account)
+    }
+
     /// Returns the number of lamports of this account (transaction wide)
     #[inline]
     pub fn get_lamports(&self) -> u64 {
@@ -953,6 +2918,22 @@ impl BorrowedAccount<'_> {
     /// Overwrites the number of lamports of this account (transaction wide)
     #[cfg(not(target_os = "solana"))]
     pub fn set_lamports(&mut self, lamports: u64) -> Result<(), InstructionError> {
+        self.can_lamports_be_changed(lamports)?;
+        // don't touch the account if the lamports do not change
+        if self.get_lamports() == lamports {
+            return Ok(());
+        }
+        self.touch()?;
+        self.transaction_context
+            .accounts
+            .record_original_lamports(self.index_in_transaction, self.get_lamports())?;
+        self.account.set_lamports(lamports);
+        Ok(())
+    }
+
+    /// Returns an error if the balance of this account may not be set to `lamports`
+    #[cfg(not(target_os = "solana"))]
+    fn can_lamports_be_changed(&self, lamports: u64) -> Result<(), InstructionError> {
         // An account not owned by the program cannot have its balance decrease
         if !self.is_owned_by_current_program() && lamports < self.get_lamports() {
             return Err(InstructionError::ExternalAccountLamportSpend);
@@ -965,12 +2946,6 @@ impl BorrowedAccount<'_> {
         if self.is_executable_internal() {
             return Err(InstructionError::ExecutableLamportChange);
         }
-        // don't touch the account if the lamports do not change
-        if self.get_lamports() == lamports {
-            return Ok(());
-        }
-        self.touch()?;
-        self.account.set_lamports(lamports);
         Ok(())
     }
 
@@ -997,9 +2972,82 @@ impl BorrowedAccount<'_> {
     /// Returns a read-only slice of the account data (transaction wide)
     #[inline]
     pub fn get_data(&self) -> &[u8] {
+        #[cfg(any(test, feature = "dev-context-only-utils"))]
+        self.transaction_context
+            .accounts
+            .mark_read(self.index_in_transaction);
         self.account.data()
     }
 
+    /// Returns `InvalidAccountData` unless the account data is exactly `expected` bytes long
+    pub fn check_data_len(&self, expected: usize) -> Result<(), InstructionError> {
+        if self.account.data().len() != expected {
+            return Err(InstructionError::InvalidAccountData);
+        }
+        Ok(())
+    }
+
+    /// Returns `InvalidAccountData` if the account data is shorter than `min` bytes
+    pub fn check_data_len_at_least(&self, min: usize) -> Result<(), InstructionError> {
+        if self.account.data().len() < min {
+            return Err(InstructionError::InvalidAccountData);
+        }
+        Ok(())
+    }
+
+    /// Returns a read-only slice of the last `len` bytes of the account data (transaction wide)
+    pub fn get_data_suffix(&self, len: usize) -> Result<&[u8], InstructionError> {
+        let data = self.get_data();
+        data.len()
+            .checked_sub(len)
+            .and_then(|start| data.get(start..))
+            .ok_or(InstructionError::AccountDataTooSmall)
+    }
+
+    /// Returns true if every byte of the account data equals `byte` (transaction wide)
+    ///
+    /// This is also true for empty account data.
+    #[cfg(not(target_os = "solana"))]
+    pub fn is_filled_with(&self, byte: u8) -> bool {
+        is_filled_with(self.get_data(), byte)
+    }
+
+    /// Returns true if the account data is all zeros or empty (transaction wide)
+    #[cfg(not(target_os = "solana"))]
+    pub fn is_data_zeroed(&self) -> bool {
+        is_zeroed(self.get_data())
+    }
+
+    /// Returns how many bytes can be written at `offset` without resizing (transaction wide)
+    ///
+    /// Zero if `offset` is at or past the end of the account data.
+    #[inline]
+    pub fn writable_len_from(&self, offset: usize) -> usize {
+        self.account.data().len().saturating_sub(offset)
+    }
+
+    /// Returns a read-only slice of `count` fixed-size records, starting at `offset` bytes into
+    /// the account data (transaction wide)
+    ///
+    /// Fails with `InvalidAccountData` if the records do not fit into the account data or if
+    /// they would not be properly aligned.
+    #[cfg(feature = "bytemuck")]
+    pub fn get_record_slice<T: bytemuck::Pod>(
+        &self,
+        offset: usize,
+        count: usize,
+    ) -> Result<&[T], InstructionError> {
+        let end = count
+            .checked_mul(std::mem::size_of::<T>())
+            .and_then(|len| len.checked_add(offset))
+            .ok_or(InstructionError::InvalidAccountData)?;
+        let data = self
+            .get_data()
+            .get(offset..end)
+            .ok_or(InstructionError::InvalidAccountData)?;
+        bytemuck::try_cast_slice(data).map_err(|_| InstructionError::InvalidAccountData)
+    }
+
     /// Returns a writable slice of the account data (transaction wide)
     #[cfg(not(target_os = "solana"))]
     pub fn get_data_mut(&mut self) -> Result<&mut [u8], InstructionError> {
@@ -1009,6 +3057,32 @@ impl BorrowedAccount<'_> {
         Ok(self.account.data_as_mut_slice())
     }
 
+    /// Overwrites every byte of the account data with `value`, without resizing (transaction wide)
+    ///
+    /// Filling a shared account with zeros does not copy its current content first.
+    /// Filling empty account data does not touch the account.
+    #[cfg(not(target_os = "solana"))]
+    pub fn fill(&mut self, value: u8) -> Result<(), InstructionError> {
+        self.can_data_be_changed()?;
+        let len = self.account.data().len();
+        if len == 0 {
+            return Ok(());
+        }
+        self.touch()?;
+        if value == 0 && self.account.is_shared() {
+            // Unshare by replacing the data with an empty buffer, then grow it back with zeros
+            // and the same spare capacity as make_data_mut() would provide.
+            self.account.set_data_from_slice(&[]);
+            self.account
+                .reserve(len.saturating_add(MAX_PERMITTED_DATA_INCREASE));
+            self.account.resize(len, 0);
+        } else {
+            self.make_data_mut();
+            self.account.data_as_mut_slice().fill(value);
+        }
+        Ok(())
+    }
+
     /// Overwrites the account data and size (transaction wide).
     ///
     /// You should always prefer set_data_from_slice(). Calling this method is
@@ -1052,7 +3126,7 @@ impl BorrowedAccount<'_> {
     pub fn set_data_length(&mut self, new_length: usize) -> Result<(), InstructionError> {
         self.can_data_be_resized(new_length)?;
         // don't touch the account if the length does not change
-        if self.get_data().len() == new_length {
+        if self.account.data().len() == new_length {
             return Ok(());
         }
         self.touch()?;
@@ -1064,7 +3138,7 @@ impl BorrowedAccount<'_> {
     /// Appends all elements in a slice to the account
     #[cfg(not(target_os = "solana"))]
     pub fn extend_from_slice(&mut self, data: &[u8]) -> Result<(), InstructionError> {
-        let new_len = self.get_data().len().saturating_add(data.len());
+        let new_len = self.account.data().len().saturating_add(data.len());
         self.can_data_be_resized(new_len)?;
 
         if data.is_empty() {
@@ -1081,6 +3155,34 @@ impl BorrowedAccount<'_> {
         Ok(())
     }
 
+    /// Overwrites the account data at `offset` with `data`, without resizing (transaction wide)
+    ///
+    /// Fails with `AccountDataTooSmall` if the range does not fit in the current data.
+    /// Writing an empty slice does not touch the account.
+    #[cfg(not(target_os = "solana"))]
+    pub fn copy_from_slice_at_offset(
+        &mut self,
+        offset: usize,
+        data: &[u8],
+    ) -> Result<(), InstructionError> {
+        let end = offset
+            .checked_add(data.len())
+            .filter(|end| *end <= self.account.data().len())
+            .ok_or(InstructionError::AccountDataTooSmall)?;
+        self.can_data_be_changed()?;
+        if data.is_empty() {
+            return Ok(());
+        }
+        self.touch()?;
+        self.make_data_mut();
+        self.account
+            .data_as_mut_slice()
+            .get_mut(offset..end)
+            .ok_or(InstructionError::AccountDataTooSmall)?
+            .copy_from_slice(data);
+        Ok(())
+    }
+
     /// Returns whether the underlying AccountSharedData is shared.
     ///
     /// The data is shared if the account has been loaded from the accounts database and has never
@@ -1111,11 +3213,31 @@ impl BorrowedAccount<'_> {
     /// Deserializes the account data into a state
     #[cfg(all(not(target_os = "solana"), feature = "bincode"))]
     pub fn get_state<T: serde::de::DeserializeOwned>(&self) -> Result<T, InstructionError> {
+        #[cfg(any(test, feature = "dev-context-only-utils"))]
+        self.transaction_context
+            .accounts
+            .mark_read(self.index_in_transaction);
         self.account
             .deserialize_data()
             .map_err(|_| InstructionError::InvalidAccountData)
     }
 
+    /// Deserializes the account data after a leading version byte into a state
+    ///
+    /// Fails with `InvalidAccountData` if the version byte is missing or not `expected_version`.
+    #[cfg(all(not(target_os = "solana"), feature = "bincode"))]
+    pub fn get_versioned_state<T: serde::de::DeserializeOwned>(
+        &self,
+        expected_version: u8,
+    ) -> Result<T, InstructionError> {
+        match self.get_data().split_first() {
+            Some((version, state)) if *version == expected_version => {
+                bincode::deserialize(state).map_err(|_| InstructionError::InvalidAccountData)
+            }
+            _ => Err(InstructionError::InvalidAccountData),
+        }
+    }
+
     /// Serializes a state into the account data
     #[cfg(all(not(target_os = "solana"), feature = "bincode"))]
     pub fn set_state<T: serde::Serialize>(&mut self, state: &T) -> Result<(), InstructionError> {
@@ -1123,6 +3245,11 @@ impl BorrowedAccount<'_> {
         let serialized_size =
             bincode::serialized_size(state).map_err(|_| InstructionError::GenericError)?;
         if serialized_size > data.len() as u64 {
+            log::debug!(
+                "set_state: serialized size {} exceeds account data length {}",
+                serialized_size,
+                data.len()
+            );
             return Err(InstructionError::AccountDataTooSmall);
         }
         bincode::serialize_into(&mut *data, state).map_err(|_| InstructionError::GenericError)?;
@@ -1155,6 +3282,13 @@ impl BorrowedAccount<'_> {
             && self.account.executable()
     }
 
+    /// Returns whether this account is executable according to the current feature set
+    #[cfg(not(target_os = "solana"))]
+    #[inline]
+    pub fn is_executable_effective(&self) -> bool {
+        self.is_executable_internal()
+    }
+
     /// Configures whether this account is executable (transaction wide)
     #[cfg(not(target_os = "solana"))]
     pub fn set_executable(&mut self, is_executable: bool) -> Result<(), InstructionError> {
@@ -1162,7 +3296,7 @@ impl BorrowedAccount<'_> {
         if !self
             .transaction_context
             .rent
-            .is_exempt(self.get_lamports(), self.get_data().len())
+            .is_exempt(self.get_lamports(), self.account.data().len())
         {
             return Err(InstructionError::ExecutableAccountNotRentExempt);
         }
@@ -1217,6 +3351,22 @@ impl BorrowedAccount<'_> {
         }
     }
 
+    /// Fails with `MissingRequiredSignature` if this account is not a signer (instruction wide)
+    pub fn require_signer(&self) -> Result<(), InstructionError> {
+        if !self.is_signer() {
+            return Err(InstructionError::MissingRequiredSignature);
+        }
+        Ok(())
+    }
+
+    /// Fails with `ReadonlyDataModified` if this account is not writable (instruction wide)
+    pub fn require_writable(&self) -> Result<(), InstructionError> {
+        if !self.is_writable() {
+            return Err(InstructionError::ReadonlyDataModified);
+        }
+        Ok(())
+    }
+
     /// Returns true if the owner of this account is the current `InstructionContext`s last program (instruction wide)
     pub fn is_owned_by_current_program(&self) -> bool {
         self.instruction_context
@@ -1246,14 +3396,16 @@ impl BorrowedAccount<'_> {
     /// Returns an error if the account data can not be resized to the given length
     #[cfg(not(target_os = "solana"))]
     pub fn can_data_be_resized(&self, new_len: usize) -> Result<(), InstructionError> {
-        let old_len = self.get_data().len();
+        let old_len = self.account.data().len();
         // Only the owner can change the length of the data
         if new_len != old_len && !self.is_owned_by_current_program() {
             return Err(InstructionError::AccountDataSizeChanged);
         }
-        self.transaction_context
-            .accounts
-            .can_data_be_resized(old_len, new_len)?;
+        self.transaction_context.accounts.can_data_be_resized(
+            self.index_in_transaction,
+            old_len,
+            new_len,
+        )?;
         self.can_data_be_changed()
     }
 
@@ -1268,48 +3420,149 @@ impl BorrowedAccount<'_> {
     fn update_accounts_resize_delta(&mut self, new_len: usize) -> Result<(), InstructionError> {
         self.transaction_context
             .accounts
-            .update_accounts_resize_delta(self.get_data().len(), new_len)
+            .update_accounts_resize_delta(self.account.data().len(), new_len)
     }
 }
 
-/// Everything that needs to be recorded from a TransactionContext after execution
+/// Tracks the lamports still available in an account while computing multiple transfers.
+///
+/// Each `reserve()` deducts from the running balance and fails with `InsufficientFunds` once
+/// the reservations would exceed it. Nothing is written to the account until `commit()`.
 #[cfg(not(target_os = "solana"))]
-pub struct ExecutionRecord {
-    pub accounts: Vec<TransactionAccount>,
-    pub return_data: TransactionReturnData,
-    pub touched_account_count: u64,
-    pub accounts_resize_delta: i64,
+pub struct LamportReservation<'a, 'b> {
+    account: &'b mut BorrowedAccount<'a>,
+    available: u64,
 }
 
-/// Used by the bank in the runtime to write back the processed accounts and recorded instructions
 #[cfg(not(target_os = "solana"))]
-impl From<TransactionContext> for ExecutionRecord {
-    fn from(context: TransactionContext) -> Self {
-        let TransactionAccounts {
-            accounts,
-            touched_flags,
-            resize_delta,
-        } = Rc::try_unwrap(context.accounts)
-            .expect("transaction_context.accounts has unexpected outstanding refs");
-        let accounts = Vec::from(Pin::into_inner(context.account_keys))
-            .into_iter()
-            .zip(accounts.into_iter().map(RefCell::into_inner))
-            .collect();
-        let touched_account_count = touched_flags
-            .borrow()
-            .iter()
-            .fold(0usize, |accumulator, was_touched| {
-                accumulator.saturating_add(*was_touched as usize)
-            }) as u64;
-        Self {
-            accounts,
-            return_data: context.return_data,
-            touched_account_count,
+impl<'a, 'b> LamportReservation<'a, 'b> {
+    /// Starts a reservation over the current balance of the given account
+    pub fn new(account: &'b mut BorrowedAccount<'a>) -> Self {
+        let available = account.get_lamports();
+        Self { account, available }
+    }
+
+    /// Returns the lamports which have not been reserved yet
+    pub fn available(&self) -> u64 {
+        self.available
+    }
+
+    /// Reserves the given amount of lamports
+    pub fn reserve(&mut self, lamports: u64) -> Result<(), InstructionError> {
+        self.available = self
+            .available
+            .checked_sub(lamports)
+            .ok_or(InstructionError::InsufficientFunds)?;
+        Ok(())
+    }
+
+    /// Deducts all reserved lamports from the account
+    pub fn commit(self) -> Result<(), InstructionError> {
+        self.account.set_lamports(self.available)
+    }
+}
+
+/// Verifies that the lamport sum of a set of accounts does not change while it is alive.
+///
+/// When dropped in a debug build the sum is recomputed and asserted to equal the recorded sum
+/// plus all deltas released via `commit_delta()`. This localizes accidental lamport changes
+/// in built-ins to the scope which introduced them, long before `pop()` reports an
+/// `UnbalancedInstruction`. The check is skipped if any of the accounts is still borrowed
+/// mutably, and it is compiled out in release builds.
+#[cfg(not(target_os = "solana"))]
+pub struct BalancedBorrowGuard<'a> {
+    transaction_context: &'a TransactionContext,
+    indices_in_transaction: Vec<IndexOfAccount>,
+    lamport_sum: u128,
+    committed_delta: i128,
+}
+
+#[cfg(not(target_os = "solana"))]
+impl BalancedBorrowGuard<'_> {
+    /// Declares an intended change of the lamport sum by the given delta
+    pub fn commit_delta(&mut self, delta: i128) {
+        self.committed_delta = self.committed_delta.saturating_add(delta);
+    }
+
+    fn lamport_sum(
+        transaction_context: &TransactionContext,
+        indices_in_transaction: &[IndexOfAccount],
+    ) -> Result<u128, InstructionError> {
+        indices_in_transaction
+            .iter()
+            .try_fold(0u128, |lamport_sum, index_in_transaction| {
+                let lamports = transaction_context
+                    .accounts
+                    .try_borrow(*index_in_transaction)?
+                    .lamports();
+                lamport_sum
+                    .checked_add(lamports as u128)
+                    .ok_or(InstructionError::ArithmeticOverflow)
+            })
+    }
+}
+
+#[cfg(not(target_os = "solana"))]
+impl Drop for BalancedBorrowGuard<'_> {
+    fn drop(&mut self) {
+        if cfg!(debug_assertions) && !std::thread::panicking() {
+            if let Ok(lamport_sum) =
+                Self::lamport_sum(self.transaction_context, &self.indices_in_transaction)
+            {
+                debug_assert_eq!(
+                    lamport_sum as i128,
+                    (self.lamport_sum as i128).saturating_add(self.committed_delta),
+                    "lamport sum of accounts {:?} changed",
+                    self.indices_in_transaction,
+                );
+            }
+        }
+    }
+}
+
+/// Everything that needs to be recorded from a TransactionContext after execution
+#[cfg(not(target_os = "solana"))]
+pub struct ExecutionRecord {
+    pub accounts: Vec<TransactionAccount>,
+    pub return_data: TransactionReturnData,
+    pub touched_account_count: u64,
+    pub accounts_resize_delta: i64,
+}
+
+/// Used by the bank in the runtime to write back the processed accounts and recorded instructions
+#[cfg(not(target_os = "solana"))]
+impl From<TransactionContext> for ExecutionRecord {
+    fn from(context: TransactionContext) -> Self {
+        let TransactionAccounts {
+            accounts,
+            touched_flags,
+            resize_delta,
+            ..
+        } = Rc::try_unwrap(context.accounts)
+            .expect("transaction_context.accounts has unexpected outstanding refs");
+        let accounts = Vec::from(Pin::into_inner(context.account_keys))
+            .into_iter()
+            .zip(accounts.into_iter().map(RefCell::into_inner))
+            .collect();
+        let touched_account_count = touched_flags
+            .borrow()
+            .iter()
+            .fold(0usize, |accumulator, was_touched| {
+                accumulator.saturating_add(*was_touched as usize)
+            }) as u64;
+        Self {
+            accounts,
+            return_data: context.return_data,
+            touched_account_count,
             accounts_resize_delta: RefCell::into_inner(resize_delta),
         }
     }
 }
 
+fn is_default_account(account: &AccountSharedData) -> bool {
+    account.lamports() == 0 && account.data().is_empty()
+}
+
 #[cfg(not(target_os = "solana"))]
 fn is_zeroed(buf: &[u8]) -> bool {
     const ZEROS_LEN: usize = 1024;
@@ -1323,6 +3576,22 @@ fn is_zeroed(buf: &[u8]) -> bool {
     }
 }
 
+#[cfg(not(target_os = "solana"))]
+fn is_filled_with(buf: &[u8], byte: u8) -> bool {
+    if byte == 0 {
+        return is_zeroed(buf);
+    }
+    const CHUNK_LEN: usize = 1024;
+    let chunk_of_bytes = [byte; CHUNK_LEN];
+    let mut chunks = buf.chunks_exact(CHUNK_LEN);
+
+    #[allow(clippy::indexing_slicing)]
+    {
+        chunks.all(|chunk| chunk == &chunk_of_bytes[..])
+            && chunks.remainder() == &chunk_of_bytes[..chunks.remainder().len()]
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -1368,4 +3637,3931 @@ mod tests {
         );
         assert_eq!(build_transaction_context(account).push(), Ok(()),);
     }
+
+    #[test]
+    fn test_privilege_bitsets() {
+        let instruction_accounts = (0..70)
+            .map(|index: IndexOfAccount| {
+                InstructionAccount::new(index, index, index % 3 == 0, index % 5 == 0)
+            })
+            .collect::<Vec<_>>();
+        let instruction_context =
+            InstructionContext::new_for_tests(0, vec![], instruction_accounts.clone(), vec![]);
+
+        let (signers, writables) = instruction_context.privilege_bitsets();
+        assert_eq!(signers.len(), 2);
+        assert_eq!(writables.len(), 2);
+        let is_bit_set = |bitset: &[u64], index: usize| {
+            bitset
+                .get(index / 64)
+                .is_some_and(|word| word & (1u64 << (index % 64)) != 0)
+        };
+        for (index, instruction_account) in instruction_accounts.iter().enumerate() {
+            assert_eq!(is_bit_set(&signers, index), instruction_account.is_signer());
+            assert_eq!(
+                is_bit_set(&writables, index),
+                instruction_account.is_writable()
+            );
+        }
+    }
+
+    #[test]
+    fn test_try_configure_max_instruction_data_len() {
+        let mut instruction_context = InstructionContext::default();
+        assert_eq!(
+            instruction_context.try_configure(
+                vec![0],
+                vec![],
+                &vec![0; MAX_INSTRUCTION_DATA_LEN.saturating_add(1)],
+            ),
+            Err(InstructionError::InvalidInstructionData),
+        );
+        assert!(instruction_context.get_instruction_data().is_empty());
+
+        let instruction_data = vec![1; MAX_INSTRUCTION_DATA_LEN];
+        assert_eq!(
+            instruction_context.try_configure(vec![0], vec![], &instruction_data),
+            Ok(()),
+        );
+        assert_eq!(instruction_context.get_instruction_data(), instruction_data);
+    }
+
+    #[test]
+    fn test_export_account_privileges() {
+        let instruction_context = InstructionContext::new_for_tests(
+            1,
+            vec![0],
+            vec![
+                InstructionAccount::new(3, 0, true, false),
+                InstructionAccount::new(1, 1, false, true),
+                InstructionAccount::new(2, 2, false, false),
+                InstructionAccount::new(3, 0, false, true),
+            ],
+            vec![],
+        );
+        let privileges = instruction_context.export_account_privileges();
+        assert_eq!(privileges, vec![(1, 0b10), (2, 0b00), (3, 0b11)]);
+        let decoded = privileges
+            .into_iter()
+            .map(|(index_in_transaction, privileges)| {
+                (
+                    index_in_transaction,
+                    InstructionContext::decode_account_privileges(privileges),
+                )
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            decoded,
+            vec![(1, (false, true)), (2, (false, false)), (3, (true, true))],
+        );
+    }
+
+    #[test]
+    fn test_find_index_of_account_with_duplicate_keys() {
+        let key_a = Pubkey::new_unique();
+        let key_b = Pubkey::new_unique();
+        let key_c = Pubkey::new_unique();
+        let transaction_context = TransactionContext::new(
+            [key_a, key_b, key_a, key_c, key_b, key_a]
+                .into_iter()
+                .map(|key| (key, AccountSharedData::default()))
+                .collect(),
+            Rent::default(),
+            1,
+            1,
+        );
+
+        for key in [key_a, key_b, key_c, Pubkey::new_unique()] {
+            assert_eq!(
+                transaction_context.find_index_of_account(&key),
+                transaction_context
+                    .account_keys
+                    .iter()
+                    .position(|candidate| *candidate == key)
+                    .map(|index| index as IndexOfAccount),
+            );
+            assert_eq!(
+                transaction_context.find_index_of_program_account(&key),
+                transaction_context
+                    .account_keys
+                    .iter()
+                    .rposition(|candidate| *candidate == key)
+                    .map(|index| index as IndexOfAccount),
+            );
+        }
+        assert_eq!(transaction_context.find_index_of_account(&key_a), Some(0));
+        assert_eq!(
+            transaction_context.find_index_of_program_account(&key_a),
+            Some(5)
+        );
+        assert_eq!(transaction_context.find_index_of_account(&key_b), Some(1));
+        assert_eq!(
+            transaction_context.find_index_of_program_account(&key_b),
+            Some(4)
+        );
+        assert_eq!(transaction_context.find_index_of_account(&key_c), Some(3));
+        assert_eq!(
+            transaction_context.find_index_of_program_account(&key_c),
+            Some(3)
+        );
+    }
+
+    #[test]
+    fn test_set_lamports_batch() {
+        let program_id = Pubkey::new_unique();
+        let other_program_id = Pubkey::new_unique();
+        let mut transaction_context = TransactionContext::new(
+            vec![
+                (program_id, AccountSharedData::default()),
+                (
+                    Pubkey::new_unique(),
+                    AccountSharedData::new(10, 0, &program_id),
+                ),
+                (
+                    Pubkey::new_unique(),
+                    AccountSharedData::new(20, 0, &other_program_id),
+                ),
+                (
+                    Pubkey::new_unique(),
+                    AccountSharedData::new(30, 0, &program_id),
+                ),
+            ],
+            Rent::default(),
+            1,
+            1,
+        );
+        transaction_context
+            .get_next_instruction_context_mut()
+            .unwrap()
+            .configure(
+                vec![0],
+                vec![
+                    InstructionAccount::new(1, 0, false, true),
+                    InstructionAccount::new(2, 1, false, true),
+                    InstructionAccount::new(3, 2, false, false),
+                ],
+                &[],
+            );
+        transaction_context.push().unwrap();
+        let lamports = |transaction_context: &TransactionContext| {
+            (1..=3)
+                .map(|index_in_transaction| {
+                    transaction_context
+                        .accounts()
+                        .try_borrow(index_in_transaction)
+                        .unwrap()
+                        .lamports()
+                })
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(
+            transaction_context.set_lamports_batch(&[(0, 5), (1, 25), (0, 5)]),
+            Err(InstructionError::DuplicateAccountIndex),
+        );
+        assert_eq!(
+            transaction_context.set_lamports_batch(&[(0, 5), (1, 15)]),
+            Err(InstructionError::ExternalAccountLamportSpend),
+        );
+        assert_eq!(
+            transaction_context.set_lamports_batch(&[(0, 5), (2, 31)]),
+            Err(InstructionError::ReadonlyLamportChange),
+        );
+        assert_eq!(
+            transaction_context.set_lamports_batch(&[(0, 5), (3, 0)]),
+            Err(InstructionError::NotEnoughAccountKeys),
+        );
+        assert_eq!(lamports(&transaction_context), vec![10, 20, 30]);
+
+        assert_eq!(
+            transaction_context.set_lamports_batch(&[(0, 5), (1, 25)]),
+            Ok(()),
+        );
+        assert_eq!(lamports(&transaction_context), vec![5, 25, 30]);
+    }
+
+    #[test]
+    fn test_instruction_data_hex() {
+        let mut instruction_context = InstructionContext::default();
+        assert_eq!(instruction_context.instruction_data_hex(), "");
+        instruction_context.configure(vec![0], vec![], &[0x00, 0x1f, 0xa0, 0xff]);
+        assert_eq!(instruction_context.instruction_data_hex(), "001fa0ff");
+        assert_eq!(
+            instruction_context.instruction_data_hex_truncated(4),
+            "001fa0ff"
+        );
+        assert_eq!(
+            instruction_context.instruction_data_hex_truncated(2),
+            "001f..."
+        );
+        assert_eq!(instruction_context.instruction_data_hex_truncated(0), "...");
+    }
+
+    #[test]
+    fn test_remaining_resize_budget() {
+        let program_id = Pubkey::new_unique();
+        let mut transaction_context = TransactionContext::new(
+            vec![
+                (program_id, AccountSharedData::default()),
+                (
+                    Pubkey::new_unique(),
+                    AccountSharedData::new(1, 16, &program_id),
+                ),
+            ],
+            Rent::default(),
+            1,
+            1,
+        );
+        transaction_context
+            .get_next_instruction_context_mut()
+            .unwrap()
+            .configure(
+                vec![0],
+                vec![InstructionAccount::new(1, 0, false, true)],
+                &[],
+            );
+        transaction_context.push().unwrap();
+        assert_eq!(
+            transaction_context.remaining_resize_budget(),
+            Ok(MAX_PERMITTED_ACCOUNTS_DATA_ALLOCATIONS_PER_TRANSACTION),
+        );
+
+        let instruction_context = transaction_context
+            .get_current_instruction_context()
+            .unwrap();
+        let mut account = instruction_context
+            .try_borrow_instruction_account(&transaction_context, 0)
+            .unwrap();
+        account.set_data_length(16 + 100).unwrap();
+        drop(account);
+        assert_eq!(
+            transaction_context.remaining_resize_budget(),
+            Ok(MAX_PERMITTED_ACCOUNTS_DATA_ALLOCATIONS_PER_TRANSACTION - 100),
+        );
+    }
+
+    #[test]
+    fn test_copy_from_slice_at_offset() {
+        let program_id = Pubkey::new_unique();
+        let mut transaction_context = TransactionContext::new(
+            vec![
+                (program_id, AccountSharedData::default()),
+                (
+                    Pubkey::new_unique(),
+                    AccountSharedData::new(1, 4, &program_id),
+                ),
+            ],
+            Rent::default(),
+            1,
+            1,
+        );
+        transaction_context
+            .get_next_instruction_context_mut()
+            .unwrap()
+            .configure(
+                vec![0],
+                vec![InstructionAccount::new(1, 0, false, true)],
+                &[],
+            );
+        transaction_context.push().unwrap();
+        let instruction_context = transaction_context
+            .get_current_instruction_context()
+            .unwrap();
+        let mut account = instruction_context
+            .try_borrow_instruction_account(&transaction_context, 0)
+            .unwrap();
+
+        // Empty writes are a no-op and do not touch the account
+        assert_eq!(account.copy_from_slice_at_offset(4, &[]), Ok(()));
+        assert_eq!(transaction_context.live_touched_count(), Ok(0));
+
+        // Past the end
+        assert_eq!(
+            account.copy_from_slice_at_offset(3, &[1, 2]),
+            Err(InstructionError::AccountDataTooSmall),
+        );
+        assert_eq!(
+            account.copy_from_slice_at_offset(usize::MAX, &[1]),
+            Err(InstructionError::AccountDataTooSmall),
+        );
+        assert_eq!(
+            account.copy_from_slice_at_offset(5, &[]),
+            Err(InstructionError::AccountDataTooSmall),
+        );
+        assert_eq!(account.get_data(), &[0, 0, 0, 0]);
+        assert_eq!(transaction_context.live_touched_count(), Ok(0));
+
+        // In range
+        assert_eq!(account.copy_from_slice_at_offset(1, &[1, 2]), Ok(()));
+        assert_eq!(account.get_data(), &[0, 1, 2, 0]);
+
+        // Up to the exact end
+        assert_eq!(account.copy_from_slice_at_offset(2, &[3, 4]), Ok(()));
+        assert_eq!(account.get_data(), &[0, 1, 3, 4]);
+        assert_eq!(transaction_context.live_touched_count(), Ok(1));
+    }
+
+    #[test]
+    fn test_trace_with_depth() {
+        let mut transaction_context = TransactionContext::new(
+            vec![(Pubkey::new_unique(), AccountSharedData::default())],
+            Rent::default(),
+            3,
+            6,
+        );
+        let push = |transaction_context: &mut TransactionContext| {
+            transaction_context
+                .get_next_instruction_context_mut()
+                .unwrap()
+                .configure(vec![0], vec![], &[]);
+            transaction_context.push().unwrap();
+        };
+        // A { B { C } D } E
+        push(&mut transaction_context);
+        push(&mut transaction_context);
+        push(&mut transaction_context);
+        transaction_context.pop().unwrap();
+        transaction_context.pop().unwrap();
+        push(&mut transaction_context);
+        transaction_context.pop().unwrap();
+        transaction_context.pop().unwrap();
+        push(&mut transaction_context);
+        transaction_context.pop().unwrap();
+
+        assert_eq!(
+            transaction_context
+                .trace_with_depth()
+                .map(|(nesting_level, _)| nesting_level)
+                .collect::<Vec<_>>(),
+            vec![0, 1, 2, 1, 0],
+        );
+    }
+
+    #[test]
+    fn test_instruction_accounts_iter() {
+        let mut transaction_context = TransactionContext::new(
+            vec![
+                (Pubkey::new_unique(), AccountSharedData::default()),
+                (
+                    Pubkey::new_unique(),
+                    AccountSharedData::new(1, 0, &Pubkey::new_unique()),
+                ),
+                (
+                    Pubkey::new_unique(),
+                    AccountSharedData::new(2, 0, &Pubkey::new_unique()),
+                ),
+            ],
+            Rent::default(),
+            1,
+            1,
+        );
+        transaction_context
+            .get_next_instruction_context_mut()
+            .unwrap()
+            .configure(
+                vec![0],
+                vec![
+                    InstructionAccount::new(1, 0, false, false),
+                    InstructionAccount::new(2, 1, false, false),
+                    InstructionAccount::new(1, 0, false, false),
+                ],
+                &[],
+            );
+        transaction_context.push().unwrap();
+        let instruction_context = transaction_context
+            .get_current_instruction_context()
+            .unwrap();
+
+        let lamports = instruction_context
+            .instruction_accounts_iter(&transaction_context)
+            .map(|account| account.map(|account| account.get_lamports()))
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        assert_eq!(lamports, vec![1, 2, 1]);
+        assert_eq!(lamports.iter().sum::<u64>(), 4);
+
+        let mut accounts = instruction_context.instruction_accounts_iter(&transaction_context);
+        let first = accounts.next().unwrap().unwrap();
+        assert!(accounts.next().unwrap().is_ok());
+        assert_eq!(
+            accounts.next().unwrap().err(),
+            Some(InstructionError::AccountBorrowFailed),
+        );
+        drop(first);
+        assert!(accounts.next().is_none());
+    }
+
+    #[test]
+    fn test_set_return_data_max_len() {
+        let mut transaction_context = TransactionContext::new(vec![], Rent::default(), 1, 1);
+        let program_id = Pubkey::new_unique();
+        assert_eq!(
+            transaction_context.set_return_data(program_id, vec![1; MAX_RETURN_DATA]),
+            Ok(()),
+        );
+        assert_eq!(transaction_context.get_return_data_len(), MAX_RETURN_DATA);
+        assert_eq!(
+            transaction_context.set_return_data(
+                Pubkey::new_unique(),
+                vec![2; MAX_RETURN_DATA.saturating_add(1)],
+            ),
+            Err(InstructionError::InvalidInstructionData),
+        );
+        assert_eq!(
+            transaction_context.get_return_data(),
+            (&program_id, [1; MAX_RETURN_DATA].as_slice()),
+        );
+    }
+
+    #[test]
+    fn test_account_access_kind() {
+        let program_id = Pubkey::new_unique();
+        let mut transaction_context = TransactionContext::new(
+            std::iter::once(program_id)
+                .chain((1..6).map(|_| Pubkey::new_unique()))
+                .map(|key| (key, AccountSharedData::new(1, 1, &program_id)))
+                .collect(),
+            Rent::default(),
+            1,
+            1,
+        );
+        transaction_context
+            .get_next_instruction_context_mut()
+            .unwrap()
+            .configure(
+                vec![0],
+                (1..6)
+                    .map(|index_in_transaction| {
+                        InstructionAccount::new(
+                            index_in_transaction,
+                            index_in_transaction - 1,
+                            false,
+                            true,
+                        )
+                    })
+                    .collect(),
+                &[],
+            );
+        transaction_context.push().unwrap();
+        let instruction_context = transaction_context
+            .get_current_instruction_context()
+            .unwrap();
+
+        // Only looking at the length does not count as reading the data
+        let account = instruction_context
+            .try_borrow_instruction_account(&transaction_context, 0)
+            .unwrap();
+        assert_eq!(account.check_data_len(1), Ok(()));
+        assert_eq!(account.check_data_len_at_least(1), Ok(()));
+        assert_eq!(account.writable_len_from(0), 1);
+        drop(account);
+        let account = instruction_context
+            .try_borrow_instruction_account(&transaction_context, 1)
+            .unwrap();
+        assert_eq!(account.get_data(), &[0]);
+        drop(account);
+        let mut account = instruction_context
+            .try_borrow_instruction_account(&transaction_context, 2)
+            .unwrap();
+        account.set_lamports(2).unwrap();
+        account.set_data_length(2).unwrap();
+        drop(account);
+        let mut account = instruction_context
+            .try_borrow_instruction_account(&transaction_context, 3)
+            .unwrap();
+        assert_eq!(account.get_data(), &[0]);
+        account.set_data_from_slice(&[1]).unwrap();
+        drop(account);
+        // Deserializing the state counts as reading the data, even if it fails
+        let account = instruction_context
+            .try_borrow_instruction_account(&transaction_context, 4)
+            .unwrap();
+        assert_eq!(
+            account.get_state::<u64>(),
+            Err(InstructionError::InvalidAccountData)
+        );
+        drop(account);
+
+        assert_eq!(
+            (1..7)
+                .map(|index_in_transaction| {
+                    transaction_context.account_access_kind(index_in_transaction)
+                })
+                .collect::<Vec<_>>(),
+            vec![
+                Ok(AccessKind::None),
+                Ok(AccessKind::ReadOnly),
+                Ok(AccessKind::WriteOnly),
+                Ok(AccessKind::ReadWrite),
+                Ok(AccessKind::ReadOnly),
+                Err(InstructionError::NotEnoughAccountKeys),
+            ],
+        );
+    }
+
+    #[test]
+    fn test_clear_return_data() {
+        let mut transaction_context = TransactionContext::new(vec![], Rent::default(), 1, 1);
+        let program_id = Pubkey::new_unique();
+        transaction_context
+            .set_return_data(program_id, vec![1, 2, 3])
+            .unwrap();
+        assert_eq!(
+            transaction_context.get_return_data_program_id(),
+            &program_id
+        );
+
+        transaction_context.clear_return_data();
+        assert_eq!(
+            transaction_context.get_return_data(),
+            (&Pubkey::default(), &[][..])
+        );
+        assert_eq!(
+            transaction_context.get_return_data_program_id(),
+            &Pubkey::default()
+        );
+    }
+
+    #[test]
+    fn test_snapshot_execution_record() {
+        let program_id = Pubkey::new_unique();
+        let mut transaction_context = TransactionContext::new(
+            vec![
+                (program_id, AccountSharedData::default()),
+                (
+                    Pubkey::new_unique(),
+                    AccountSharedData::new(1, 1, &program_id),
+                ),
+            ],
+            Rent::default(),
+            1,
+            1,
+        );
+        transaction_context
+            .get_next_instruction_context_mut()
+            .unwrap()
+            .configure(
+                vec![0],
+                vec![InstructionAccount::new(1, 0, false, true)],
+                &[],
+            );
+        transaction_context.push().unwrap();
+        let instruction_context = transaction_context
+            .get_current_instruction_context()
+            .unwrap();
+        let mut account = instruction_context
+            .try_borrow_instruction_account(&transaction_context, 0)
+            .unwrap();
+        account.set_data_length(3).unwrap();
+        assert_eq!(
+            transaction_context.snapshot_execution_record().err(),
+            Some(InstructionError::AccountBorrowFailed),
+        );
+        drop(account);
+        transaction_context
+            .set_return_data(program_id, vec![1, 2, 3])
+            .unwrap();
+
+        let snapshot = transaction_context.snapshot_execution_record().unwrap();
+        let account = transaction_context
+            .get_current_instruction_context()
+            .unwrap()
+            .try_borrow_instruction_account(&transaction_context, 0)
+            .unwrap();
+        assert!(!account.is_shared());
+        drop(account);
+        transaction_context.pop().unwrap();
+        let record = ExecutionRecord::from(transaction_context);
+        assert_eq!(snapshot.accounts, record.accounts);
+        assert_eq!(snapshot.return_data, record.return_data);
+        assert_eq!(snapshot.touched_account_count, 1);
+        assert_eq!(snapshot.touched_account_count, record.touched_account_count);
+        assert_eq!(snapshot.accounts_resize_delta, 2);
+        assert_eq!(snapshot.accounts_resize_delta, record.accounts_resize_delta);
+    }
+
+    #[test]
+    fn test_try_new_max_accounts() {
+        let transaction_accounts = vec![
+            (Pubkey::new_unique(), AccountSharedData::default());
+            IndexOfAccount::MAX as usize
+        ];
+        let transaction_context =
+            TransactionContext::try_new(transaction_accounts.clone(), Rent::default(), 1, 1)
+                .unwrap();
+        assert_eq!(
+            transaction_context.get_number_of_accounts(),
+            IndexOfAccount::MAX
+        );
+
+        let mut transaction_accounts = transaction_accounts;
+        transaction_accounts.push((Pubkey::new_unique(), AccountSharedData::default()));
+        assert_eq!(
+            TransactionContext::try_new(transaction_accounts, Rent::default(), 1, 1).err(),
+            Some(InstructionError::MaxAccountsExceeded),
+        );
+    }
+
+    #[test]
+    fn test_is_executing_program() {
+        let program_id = Pubkey::new_unique();
+        let transaction_context = TransactionContext::new(
+            vec![
+                (Pubkey::new_unique(), AccountSharedData::default()),
+                (program_id, AccountSharedData::default()),
+                (Pubkey::new_unique(), AccountSharedData::default()),
+                (program_id, AccountSharedData::default()),
+            ],
+            Rent::default(),
+            1,
+            1,
+        );
+        let instruction_context = InstructionContext::new_for_tests(0, vec![0, 1], vec![], vec![]);
+        assert_eq!(
+            (0..transaction_context.get_number_of_accounts())
+                .map(|index_in_transaction| {
+                    instruction_context.is_executing_program(index_in_transaction)
+                })
+                .collect::<Vec<_>>(),
+            vec![Ok(false), Ok(true), Ok(false), Ok(false)],
+        );
+
+        let instruction_context = InstructionContext::new_for_tests(0, vec![], vec![], vec![]);
+        assert_eq!(
+            instruction_context.is_executing_program(0),
+            Err(InstructionError::NotEnoughAccountKeys),
+        );
+    }
+
+    #[test]
+    fn test_is_data_zeroed() {
+        let mut data = vec![0; 5 * 1024];
+        let transaction_context = TransactionContext::new(
+            vec![(
+                Pubkey::new_unique(),
+                AccountSharedData::create(1, data.clone(), Pubkey::new_unique(), false, 0),
+            )],
+            Rent::default(),
+            1,
+            1,
+        );
+        let instruction_context = InstructionContext::new_for_tests(0, vec![0], vec![], vec![]);
+        let account = instruction_context
+            .try_borrow_program_account(&transaction_context, 0)
+            .unwrap();
+        assert!(account.is_data_zeroed());
+        drop(account);
+
+        *data.last_mut().unwrap() = 1;
+        let transaction_context = TransactionContext::new(
+            vec![(
+                Pubkey::new_unique(),
+                AccountSharedData::create(1, data, Pubkey::new_unique(), false, 0),
+            )],
+            Rent::default(),
+            1,
+            1,
+        );
+        let account = instruction_context
+            .try_borrow_program_account(&transaction_context, 0)
+            .unwrap();
+        assert!(!account.is_data_zeroed());
+    }
+
+    #[test]
+    fn test_rent_collection_summary() {
+        let rent = Rent::default();
+        let minimum_balance = rent.minimum_balance(10);
+        let transaction_context = TransactionContext::new(
+            vec![
+                (
+                    Pubkey::new_unique(),
+                    AccountSharedData::new(minimum_balance, 10, &Pubkey::new_unique()),
+                ),
+                (
+                    Pubkey::new_unique(),
+                    AccountSharedData::new(minimum_balance - 1, 10, &Pubkey::new_unique()),
+                ),
+                (
+                    Pubkey::new_unique(),
+                    AccountSharedData::new(1, 10, &Pubkey::new_unique()),
+                ),
+            ],
+            rent,
+            1,
+            1,
+        );
+        assert_eq!(
+            transaction_context.rent_collection_summary(),
+            Ok(RentSummary {
+                accounts: vec![
+                    AccountRentState {
+                        is_rent_exempt: true,
+                        deficit: 0,
+                    },
+                    AccountRentState {
+                        is_rent_exempt: false,
+                        deficit: 1,
+                    },
+                    AccountRentState {
+                        is_rent_exempt: false,
+                        deficit: minimum_balance - 1,
+                    },
+                ],
+                total_deficit: minimum_balance,
+            }),
+        );
+    }
+
+    #[test]
+    fn test_is_account_touched() {
+        let transaction_context = TransactionContext::new(
+            vec![
+                (Pubkey::new_unique(), AccountSharedData::default()),
+                (Pubkey::new_unique(), AccountSharedData::default()),
+            ],
+            Rent::default(),
+            1,
+            1,
+        );
+        assert_eq!(transaction_context.is_account_touched(0), Ok(false));
+        assert_eq!(transaction_context.is_account_touched(1), Ok(false));
+        assert_eq!(
+            transaction_context.is_account_touched(2),
+            Err(InstructionError::NotEnoughAccountKeys),
+        );
+
+        transaction_context.accounts().touch(1).unwrap();
+        assert_eq!(transaction_context.is_account_touched(0), Ok(false));
+        assert_eq!(transaction_context.is_account_touched(1), Ok(true));
+        assert_eq!(transaction_context.accounts().is_touched(1), Ok(true));
+    }
+
+    #[test]
+    fn test_check_no_lamport_creation() {
+        let transaction_context = TransactionContext::new(
+            vec![
+                (
+                    Pubkey::new_unique(),
+                    AccountSharedData::new(u64::MAX, 0, &Pubkey::new_unique()),
+                ),
+                (
+                    Pubkey::new_unique(),
+                    AccountSharedData::new(2, 0, &Pubkey::new_unique()),
+                ),
+            ],
+            Rent::default(),
+            1,
+            1,
+        );
+        let initial_total = transaction_context.total_lamports().unwrap();
+        assert_eq!(initial_total, u64::MAX as u128 + 2);
+        assert_eq!(
+            transaction_context.check_no_lamport_creation(initial_total),
+            Ok(())
+        );
+
+        transaction_context
+            .try_borrow_account_mut_by_index(1)
+            .unwrap()
+            .set_lamports(1);
+        assert_eq!(
+            transaction_context.check_no_lamport_creation(initial_total),
+            Ok(())
+        );
+        transaction_context
+            .try_borrow_account_mut_by_index(1)
+            .unwrap()
+            .set_lamports(3);
+        assert_eq!(
+            transaction_context.check_no_lamport_creation(initial_total),
+            Err(InstructionError::UnbalancedInstruction),
+        );
+    }
+
+    #[cfg(feature = "bincode")]
+    #[test]
+    fn test_instruction_context_serde_round_trip() {
+        let instruction_context = InstructionContext::new_for_tests(
+            1,
+            vec![0, 3],
+            vec![
+                InstructionAccount::new(1, 0, true, false),
+                InstructionAccount::new(2, 1, false, true),
+                InstructionAccount::new(1, 0, true, false),
+            ],
+            vec![1, 2, 3],
+        );
+        let serialized = bincode::serialize(&instruction_context).unwrap();
+        assert_eq!(
+            bincode::deserialize::<InstructionContext>(&serialized).unwrap(),
+            instruction_context,
+        );
+    }
+
+    #[test]
+    fn test_current_instruction_lamport_sum() {
+        let mut transaction_context = TransactionContext::new(
+            vec![
+                (Pubkey::new_unique(), AccountSharedData::default()),
+                (
+                    Pubkey::new_unique(),
+                    AccountSharedData::new(10, 0, &Pubkey::new_unique()),
+                ),
+                (
+                    Pubkey::new_unique(),
+                    AccountSharedData::new(20, 0, &Pubkey::new_unique()),
+                ),
+            ],
+            Rent::default(),
+            1,
+            1,
+        );
+        transaction_context
+            .get_next_instruction_context_mut()
+            .unwrap()
+            .configure(
+                vec![0],
+                vec![
+                    InstructionAccount::new(1, 0, false, true),
+                    InstructionAccount::new(2, 1, false, true),
+                    InstructionAccount::new(1, 0, false, true),
+                ],
+                &[],
+            );
+        transaction_context.push().unwrap();
+        assert_eq!(
+            transaction_context.expected_instruction_lamport_sum(),
+            Ok(30)
+        );
+        assert_eq!(
+            transaction_context.current_instruction_lamport_sum(),
+            Ok(30)
+        );
+
+        transaction_context
+            .try_borrow_account_mut_by_index(2)
+            .unwrap()
+            .set_lamports(25);
+        assert_eq!(
+            transaction_context.expected_instruction_lamport_sum(),
+            Ok(30)
+        );
+        assert_eq!(
+            transaction_context.current_instruction_lamport_sum(),
+            Ok(35)
+        );
+        assert_eq!(
+            transaction_context.pop(),
+            Err(InstructionError::UnbalancedInstruction)
+        );
+    }
+
+    #[test]
+    fn test_writable_but_unowned_indices() {
+        let program_id = Pubkey::new_unique();
+        let transaction_context = TransactionContext::new(
+            vec![
+                (program_id, AccountSharedData::default()),
+                (
+                    Pubkey::new_unique(),
+                    AccountSharedData::new(1, 0, &program_id),
+                ),
+                (
+                    Pubkey::new_unique(),
+                    AccountSharedData::new(1, 0, &Pubkey::new_unique()),
+                ),
+                (
+                    Pubkey::new_unique(),
+                    AccountSharedData::new(1, 0, &Pubkey::new_unique()),
+                ),
+            ],
+            Rent::default(),
+            1,
+            1,
+        );
+        let instruction_context = InstructionContext::new_for_tests(
+            0,
+            vec![0],
+            vec![
+                InstructionAccount::new(1, 0, false, true),
+                InstructionAccount::new(2, 1, false, true),
+                InstructionAccount::new(3, 2, false, false),
+            ],
+            vec![],
+        );
+        assert_eq!(
+            instruction_context.writable_but_unowned_indices(&transaction_context),
+            Ok(vec![1]),
+        );
+    }
+
+    #[test]
+    fn test_fill() {
+        let program_id = Pubkey::new_unique();
+        let shared_account = AccountSharedData::create(1, vec![1, 2, 3], program_id, false, 0);
+        let mut transaction_context = TransactionContext::new(
+            vec![
+                (program_id, AccountSharedData::default()),
+                (Pubkey::new_unique(), shared_account.clone()),
+                (
+                    Pubkey::new_unique(),
+                    AccountSharedData::create(1, vec![1, 2, 3], program_id, false, 0),
+                ),
+                (
+                    Pubkey::new_unique(),
+                    AccountSharedData::new(1, 0, &program_id),
+                ),
+            ],
+            Rent::default(),
+            1,
+            1,
+        );
+        transaction_context
+            .get_next_instruction_context_mut()
+            .unwrap()
+            .configure(
+                vec![0],
+                vec![
+                    InstructionAccount::new(1, 0, false, true),
+                    InstructionAccount::new(2, 1, false, true),
+                    InstructionAccount::new(3, 2, false, true),
+                ],
+                &[],
+            );
+        transaction_context.push().unwrap();
+        let instruction_context = transaction_context
+            .get_current_instruction_context()
+            .unwrap();
+
+        // Zero fill a shared account
+        let mut account = instruction_context
+            .try_borrow_instruction_account(&transaction_context, 0)
+            .unwrap();
+        assert!(account.is_shared());
+        assert_eq!(account.fill(0), Ok(()));
+        assert!(!account.is_shared());
+        assert_eq!(account.get_data(), &[0, 0, 0]);
+        assert_eq!(shared_account.data(), &[1, 2, 3]);
+        drop(account);
+
+        // Non-zero fill an owned account
+        let mut account = instruction_context
+            .try_borrow_instruction_account(&transaction_context, 1)
+            .unwrap();
+        account.get_data_mut().unwrap();
+        assert!(!account.is_shared());
+        assert_eq!(account.fill(7), Ok(()));
+        assert_eq!(account.get_data(), &[7, 7, 7]);
+        drop(account);
+        assert_eq!(transaction_context.live_touched_count(), Ok(2));
+
+        // Empty data is not touched
+        let mut account = instruction_context
+            .try_borrow_instruction_account(&transaction_context, 2)
+            .unwrap();
+        assert_eq!(account.fill(7), Ok(()));
+        drop(account);
+        assert_eq!(transaction_context.is_account_touched(3), Ok(false));
+    }
+
+    #[test]
+    fn test_folded_stacks() {
+        let program_ids: [Pubkey; 5] = std::array::from_fn(|_| Pubkey::new_unique());
+        let mut transaction_context = TransactionContext::new(
+            program_ids
+                .iter()
+                .map(|program_id| (*program_id, AccountSharedData::default()))
+                .collect(),
+            Rent::default(),
+            3,
+            6,
+        );
+        let push = |transaction_context: &mut TransactionContext, program_account| {
+            transaction_context
+                .get_next_instruction_context_mut()
+                .unwrap()
+                .configure(vec![program_account], vec![], &[]);
+            transaction_context.push().unwrap();
+        };
+        // A { B { C } D } E
+        push(&mut transaction_context, 0);
+        push(&mut transaction_context, 1);
+        push(&mut transaction_context, 2);
+        transaction_context.pop().unwrap();
+        transaction_context.pop().unwrap();
+        push(&mut transaction_context, 3);
+        transaction_context.pop().unwrap();
+        transaction_context.pop().unwrap();
+        push(&mut transaction_context, 4);
+        transaction_context.pop().unwrap();
+
+        let [a, b, c, d, e] = program_ids.map(|program_id| program_id.to_string());
+        assert_eq!(
+            transaction_context.folded_stacks(),
+            vec![format!("{a};{b};{c}"), format!("{a};{d}"), e],
+        );
+    }
+
+    #[test]
+    fn test_get_parent_instruction_context() {
+        let mut transaction_context = TransactionContext::new(
+            vec![
+                (Pubkey::new_unique(), AccountSharedData::default()),
+                (Pubkey::new_unique(), AccountSharedData::default()),
+            ],
+            Rent::default(),
+            2,
+            2,
+        );
+        assert_eq!(
+            transaction_context.get_parent_instruction_context(),
+            Err(InstructionError::CallDepth),
+        );
+
+        transaction_context
+            .get_next_instruction_context_mut()
+            .unwrap()
+            .configure(vec![0], vec![], &[]);
+        transaction_context.push().unwrap();
+        assert_eq!(
+            transaction_context.get_parent_instruction_context(),
+            Ok(None)
+        );
+
+        transaction_context
+            .get_next_instruction_context_mut()
+            .unwrap()
+            .configure(vec![1], vec![], &[]);
+        transaction_context.push().unwrap();
+        let parent = transaction_context
+            .get_parent_instruction_context()
+            .unwrap()
+            .unwrap();
+        assert_eq!(parent.get_number_of_program_accounts(), 1);
+        assert_eq!(parent.get_index_of_program_account_in_transaction(0), Ok(0));
+        assert_eq!(parent.get_stack_height(), 1);
+    }
+
+    #[test]
+    fn test_accounts_closed() {
+        let transaction_context = TransactionContext::new(
+            vec![
+                (Pubkey::new_unique(), AccountSharedData::default()),
+                (
+                    Pubkey::new_unique(),
+                    AccountSharedData::new(1, 0, &Pubkey::new_unique()),
+                ),
+                (
+                    Pubkey::new_unique(),
+                    AccountSharedData::new(1, 0, &Pubkey::new_unique()),
+                ),
+            ],
+            Rent::default(),
+            1,
+            1,
+        );
+        for index_in_transaction in [1, 2] {
+            let mut account = transaction_context
+                .try_borrow_account_mut_by_index(index_in_transaction)
+                .unwrap();
+            account.set_lamports(0);
+            account.set_owner(solana_sdk_ids::system_program::id());
+            transaction_context
+                .accounts()
+                .touch(index_in_transaction)
+                .unwrap();
+        }
+        transaction_context
+            .try_borrow_account_mut_by_index(2)
+            .unwrap()
+            .set_lamports(1);
+
+        // The untouched default account is not reported
+        assert_eq!(transaction_context.accounts_closed(), Ok(vec![1]));
+    }
+
+    #[test]
+    fn test_indices_of_instruction_account_in_transaction() {
+        let instruction_context = InstructionContext::new_for_tests(
+            0,
+            vec![0],
+            vec![
+                InstructionAccount::new(1, 0, false, false),
+                InstructionAccount::new(2, 1, false, true),
+                InstructionAccount::new(3, 2, true, false),
+                InstructionAccount::new(2, 1, false, true),
+            ],
+            vec![],
+        );
+        assert_eq!(
+            instruction_context
+                .indices_of_instruction_account_in_transaction(2)
+                .collect::<Vec<_>>(),
+            vec![1, 3],
+        );
+        assert_eq!(
+            instruction_context
+                .indices_of_instruction_account_in_transaction(3)
+                .collect::<Vec<_>>(),
+            vec![2],
+        );
+        assert_eq!(
+            instruction_context
+                .indices_of_instruction_account_in_transaction(0)
+                .next(),
+            None,
+        );
+    }
+
+    #[test]
+    fn test_validate_account_privileges() {
+        let instruction_context = InstructionContext::new_for_tests(
+            0,
+            vec![0],
+            vec![
+                InstructionAccount::new(1, 0, true, false),
+                InstructionAccount::new(2, 1, false, true),
+            ],
+            vec![],
+        );
+        assert_eq!(
+            instruction_context.validate_account_privileges(&[(true, false), (false, true)]),
+            Ok(()),
+        );
+        assert_eq!(
+            instruction_context.validate_account_privileges(&[(true, false)]),
+            Ok(()),
+        );
+        assert_eq!(
+            instruction_context.validate_account_privileges(&[(true, true)]),
+            Err(InstructionError::ReadonlyDataModified),
+        );
+        assert_eq!(
+            instruction_context.validate_account_privileges(&[(true, false), (true, true)]),
+            Err(InstructionError::MissingRequiredSignature),
+        );
+        assert_eq!(
+            instruction_context.validate_account_privileges(&[(false, false)]),
+            Err(InstructionError::InvalidArgument),
+        );
+        assert_eq!(
+            instruction_context.validate_account_privileges(&[(true, false), (false, false)]),
+            Err(InstructionError::InvalidArgument),
+        );
+        assert_eq!(
+            instruction_context.validate_account_privileges(&[
+                (true, false),
+                (false, true),
+                (false, false),
+            ]),
+            Err(InstructionError::NotEnoughAccountKeys),
+        );
+    }
+
+    #[test]
+    fn test_touch_all() {
+        let transaction_context = TransactionContext::new(
+            vec![(Pubkey::new_unique(), AccountSharedData::default()); 3],
+            Rent::default(),
+            1,
+            1,
+        );
+        let accounts = transaction_context.accounts();
+        let touched = || {
+            (0..3)
+                .map(|index| accounts.is_touched(index).unwrap())
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(accounts.touch_all(&[]), Ok(()));
+        assert_eq!(touched(), vec![false, false, false]);
+
+        assert_eq!(
+            accounts.touch_all(&[0, 3, 2]),
+            Err(InstructionError::NotEnoughAccountKeys),
+        );
+        assert_eq!(touched(), vec![false, false, false]);
+
+        assert_eq!(accounts.touch_all(&[2, 0, 2]), Ok(()));
+        assert_eq!(touched(), vec![true, false, true]);
+    }
+
+    #[test]
+    fn test_find_accounts_by_owner() {
+        let owner = Pubkey::new_unique();
+        let transaction_context = TransactionContext::new(
+            vec![
+                (Pubkey::new_unique(), AccountSharedData::new(1, 0, &owner)),
+                (
+                    Pubkey::new_unique(),
+                    AccountSharedData::new(1, 0, &Pubkey::new_unique()),
+                ),
+                (Pubkey::new_unique(), AccountSharedData::new(1, 0, &owner)),
+            ],
+            Rent::default(),
+            1,
+            1,
+        );
+        assert_eq!(
+            transaction_context.find_accounts_by_owner(&owner),
+            Ok(vec![0, 2])
+        );
+
+        let account = transaction_context
+            .try_borrow_account_mut_by_index(1)
+            .unwrap();
+        assert_eq!(
+            transaction_context.find_accounts_by_owner(&owner),
+            Err(InstructionError::AccountBorrowFailed),
+        );
+        drop(account);
+    }
+
+    #[test]
+    fn test_is_signer_anywhere() {
+        let program_id = Pubkey::new_unique();
+        let signer_key = Pubkey::new_unique();
+        let mut transaction_context = TransactionContext::new(
+            vec![
+                (program_id, AccountSharedData::default()),
+                (signer_key, AccountSharedData::default()),
+            ],
+            Rent::default(),
+            1,
+            1,
+        );
+        transaction_context
+            .get_next_instruction_context_mut()
+            .unwrap()
+            .configure(
+                vec![0],
+                vec![InstructionAccount::new(1, 0, true, false)],
+                &[],
+            );
+        // Configured but not pushed yet
+        assert_eq!(
+            transaction_context.is_signer_anywhere(&signer_key),
+            Ok(false)
+        );
+
+        transaction_context.push().unwrap();
+        assert_eq!(
+            transaction_context.is_signer_anywhere(&signer_key),
+            Ok(true)
+        );
+        assert_eq!(
+            transaction_context.is_signer_anywhere(&program_id),
+            Ok(false)
+        );
+        assert_eq!(
+            transaction_context.is_signer_anywhere(&Pubkey::new_unique()),
+            Ok(false)
+        );
+    }
+
+    #[test]
+    fn test_signer_bitset() {
+        let transaction_accounts = (0..66)
+            .map(|_| (Pubkey::new_unique(), AccountSharedData::default()))
+            .collect::<Vec<_>>();
+        let mut transaction_context =
+            TransactionContext::new(transaction_accounts, Rent::default(), 1, 2);
+        transaction_context
+            .get_next_instruction_context_mut()
+            .unwrap()
+            .configure(
+                vec![0],
+                vec![
+                    InstructionAccount::new(1, 0, true, false),
+                    InstructionAccount::new(2, 1, false, true),
+                    InstructionAccount::new(65, 2, true, true),
+                ],
+                &[],
+            );
+        transaction_context.push().unwrap();
+        transaction_context.pop().unwrap();
+        // Configured but not pushed yet
+        transaction_context
+            .get_next_instruction_context_mut()
+            .unwrap()
+            .configure(
+                vec![0],
+                vec![InstructionAccount::new(3, 0, true, false)],
+                &[],
+            );
+
+        let signers = transaction_context.signer_bitset().unwrap();
+        assert_eq!(signers, vec![0b10, 0b10]);
+        for index_in_transaction in 0..66 {
+            assert_eq!(
+                TransactionContext::is_bit_set(&signers, index_in_transaction),
+                matches!(index_in_transaction, 1 | 65),
+            );
+        }
+        assert!(!TransactionContext::is_bit_set(&signers, 128));
+    }
+
+    #[test]
+    fn test_all_program_account_indices() {
+        let transaction_accounts = (0..4)
+            .map(|_| (Pubkey::new_unique(), AccountSharedData::default()))
+            .collect::<Vec<_>>();
+        let mut transaction_context =
+            TransactionContext::new(transaction_accounts, Rent::default(), 1, 3);
+        assert!(transaction_context.all_program_account_indices().is_empty());
+        for program_accounts in [vec![2, 0], vec![0]] {
+            transaction_context
+                .get_next_instruction_context_mut()
+                .unwrap()
+                .configure(program_accounts, vec![], &[]);
+            transaction_context.push().unwrap();
+            transaction_context.pop().unwrap();
+        }
+        // Configured but not pushed yet
+        transaction_context
+            .get_next_instruction_context_mut()
+            .unwrap()
+            .configure(vec![3], vec![], &[]);
+        assert_eq!(
+            transaction_context.all_program_account_indices(),
+            vec![0, 2]
+        );
+    }
+
+    #[test]
+    fn test_writable_account_keys_sorted() {
+        let keys: [Pubkey; 4] = std::array::from_fn(|_| Pubkey::new_unique());
+        let mut transaction_context = TransactionContext::new(
+            keys.iter()
+                .map(|key| (*key, AccountSharedData::default()))
+                .collect(),
+            Rent::default(),
+            1,
+            2,
+        );
+        transaction_context
+            .get_next_instruction_context_mut()
+            .unwrap()
+            .configure(
+                vec![0],
+                vec![
+                    InstructionAccount::new(2, 0, false, true),
+                    InstructionAccount::new(1, 1, false, false),
+                    InstructionAccount::new(1, 2, false, true),
+                    InstructionAccount::new(2, 0, false, true),
+                ],
+                &[],
+            );
+        transaction_context.push().unwrap();
+        transaction_context.pop().unwrap();
+        // Configured but not pushed yet
+        transaction_context
+            .get_next_instruction_context_mut()
+            .unwrap()
+            .configure(
+                vec![0],
+                vec![InstructionAccount::new(3, 0, false, true)],
+                &[],
+            );
+
+        let [_, key_1, key_2, _] = keys;
+        let mut expected = vec![key_1, key_2];
+        expected.sort_unstable();
+        assert_eq!(
+            transaction_context.writable_account_keys_sorted(),
+            Ok(expected)
+        );
+    }
+
+    #[test]
+    fn test_is_filled_with() {
+        let mut data = vec![7; 2 * 1024 + 3];
+        let transaction_context = TransactionContext::new(
+            vec![
+                (
+                    Pubkey::new_unique(),
+                    AccountSharedData::create(1, data.clone(), Pubkey::new_unique(), false, 0),
+                ),
+                (
+                    Pubkey::new_unique(),
+                    AccountSharedData::create(1, vec![], Pubkey::new_unique(), false, 0),
+                ),
+            ],
+            Rent::default(),
+            1,
+            1,
+        );
+        let instruction_context = InstructionContext::new_for_tests(0, vec![0, 1], vec![], vec![]);
+        let account = instruction_context
+            .try_borrow_program_account(&transaction_context, 0)
+            .unwrap();
+        assert!(account.is_filled_with(7));
+        assert!(!account.is_filled_with(0));
+        drop(account);
+        let account = instruction_context
+            .try_borrow_program_account(&transaction_context, 1)
+            .unwrap();
+        assert!(account.is_filled_with(0));
+        assert!(account.is_filled_with(7));
+        drop(account);
+
+        *data.last_mut().unwrap() = 0;
+        let transaction_context = TransactionContext::new(
+            vec![(
+                Pubkey::new_unique(),
+                AccountSharedData::create(1, data, Pubkey::new_unique(), false, 0),
+            )],
+            Rent::default(),
+            1,
+            1,
+        );
+        let account = instruction_context
+            .try_borrow_program_account(&transaction_context, 0)
+            .unwrap();
+        assert!(!account.is_filled_with(7));
+        assert!(!account.is_filled_with(0));
+    }
+
+    #[test]
+    fn test_read_access_order() {
+        let transaction_context = TransactionContext::new(
+            (0..4)
+                .map(|_| (Pubkey::new_unique(), AccountSharedData::default()))
+                .collect(),
+            Rent::default(),
+            1,
+            1,
+        );
+        let instruction_context =
+            InstructionContext::new_for_tests(0, vec![3, 0, 2], vec![], vec![]);
+        let read_in_order = |order: &[IndexOfAccount]| {
+            for index_in_instruction in order {
+                instruction_context
+                    .try_borrow_program_account(&transaction_context, *index_in_instruction)
+                    .unwrap()
+                    .get_data();
+            }
+        };
+
+        read_in_order(&[0]);
+        assert_eq!(transaction_context.read_access_order(), Ok(vec![]));
+
+        transaction_context.set_record_read_accesses(true).unwrap();
+        read_in_order(&[2, 0, 2, 1, 0]);
+        assert_eq!(transaction_context.read_access_order(), Ok(vec![2, 3, 0]));
+
+        // Borrowing without reading the data is not recorded
+        transaction_context.set_record_read_accesses(true).unwrap();
+        instruction_context
+            .try_borrow_program_account(&transaction_context, 0)
+            .unwrap();
+        read_in_order(&[1]);
+        assert_eq!(transaction_context.read_access_order(), Ok(vec![0]));
+
+        transaction_context.set_record_read_accesses(false).unwrap();
+        read_in_order(&[2]);
+        assert_eq!(transaction_context.read_access_order(), Ok(vec![]));
+    }
+
+    #[test]
+    fn test_accounts_needing_rent_update() {
+        let rent = Rent::default();
+        let program_id = Pubkey::new_unique();
+        let exempt_lamports = rent.minimum_balance(1);
+        let mut transaction_context = TransactionContext::new(
+            vec![
+                (program_id, AccountSharedData::default()),
+                // Grows out of rent exemption
+                (
+                    Pubkey::new_unique(),
+                    AccountSharedData::new(exempt_lamports, 1, &program_id),
+                ),
+                // Withdraws out of rent exemption
+                (
+                    Pubkey::new_unique(),
+                    AccountSharedData::new(exempt_lamports, 1, &program_id),
+                ),
+                // Grows but stays rent exempt
+                (
+                    Pubkey::new_unique(),
+                    AccountSharedData::new(rent.minimum_balance(2), 1, &program_id),
+                ),
+                // Grows but was not rent exempt to begin with
+                (
+                    Pubkey::new_unique(),
+                    AccountSharedData::new(exempt_lamports.saturating_sub(1), 1, &program_id),
+                ),
+                // Not modified
+                (
+                    Pubkey::new_unique(),
+                    AccountSharedData::new(exempt_lamports, 1, &program_id),
+                ),
+            ],
+            rent,
+            1,
+            1,
+        );
+        transaction_context
+            .get_next_instruction_context_mut()
+            .unwrap()
+            .configure(
+                vec![0],
+                (1..6)
+                    .map(|index_in_transaction| {
+                        InstructionAccount::new(
+                            index_in_transaction,
+                            index_in_transaction.saturating_sub(1),
+                            false,
+                            true,
+                        )
+                    })
+                    .collect(),
+                &[],
+            );
+        transaction_context.push().unwrap();
+        assert_eq!(
+            transaction_context.accounts_needing_rent_update(),
+            Ok(vec![])
+        );
+        assert!(transaction_context
+            .accounts
+            .original_lamports
+            .borrow()
+            .is_none());
+
+        let instruction_context = transaction_context
+            .get_current_instruction_context()
+            .unwrap();
+        for index_in_instruction in [0, 2, 3] {
+            instruction_context
+                .try_borrow_instruction_account(&transaction_context, index_in_instruction)
+                .unwrap()
+                .set_data_from_slice(&[0; 2])
+                .unwrap();
+        }
+        let mut account = instruction_context
+            .try_borrow_instruction_account(&transaction_context, 1)
+            .unwrap();
+        account.set_lamports(0).unwrap();
+        account
+            .set_lamports(exempt_lamports.saturating_sub(1))
+            .unwrap();
+        drop(account);
+        assert_eq!(
+            transaction_context.accounts_needing_rent_update(),
+            Ok(vec![1, 2])
+        );
+    }
+
+    #[test]
+    fn test_diff() {
+        let keys: [Pubkey; 4] = std::array::from_fn(|_| Pubkey::new_unique());
+        let owner = Pubkey::new_unique();
+        let transaction_accounts = keys
+            .iter()
+            .map(|key| (*key, AccountSharedData::new(1, 1, &owner)))
+            .collect::<Vec<_>>();
+        let transaction_context =
+            TransactionContext::new(transaction_accounts.clone(), Rent::default(), 1, 1);
+        let mut other_transaction_accounts = transaction_accounts.clone();
+        let other_key = Pubkey::new_unique();
+        if let [_, (_, account_1), (_, account_2), (key_3, _)] =
+            other_transaction_accounts.as_mut_slice()
+        {
+            account_1.set_lamports(2);
+            account_1.set_data_from_slice(&[1]);
+            account_2.set_rent_epoch(1);
+            *key_3 = other_key;
+        }
+        let other_transaction_context =
+            TransactionContext::new(other_transaction_accounts, Rent::default(), 1, 1);
+
+        assert_eq!(
+            transaction_context.diff(&transaction_context.clone()),
+            Ok(vec![])
+        );
+        let [_, key_1, key_2, key_3] = keys;
+        let no_diff = |index_in_transaction, key| AccountDiff {
+            index_in_transaction,
+            key,
+            other_key: None,
+            lamports: false,
+            owner: false,
+            data: false,
+            executable: false,
+            rent_epoch: false,
+        };
+        assert_eq!(
+            transaction_context.diff(&other_transaction_context),
+            Ok(vec![
+                AccountDiff {
+                    lamports: true,
+                    data: true,
+                    ..no_diff(1, key_1)
+                },
+                AccountDiff {
+                    rent_epoch: true,
+                    ..no_diff(2, key_2)
+                },
+                AccountDiff {
+                    other_key: Some(other_key),
+                    ..no_diff(3, key_3)
+                },
+            ]),
+        );
+
+        let borrowed_account = other_transaction_context
+            .try_borrow_account_mut_by_index(0)
+            .unwrap();
+        assert_eq!(
+            transaction_context.diff(&other_transaction_context),
+            Err(InstructionError::AccountBorrowFailed),
+        );
+        drop(borrowed_account);
+
+        let shorter_transaction_context = TransactionContext::new(
+            transaction_accounts.into_iter().take(3).collect(),
+            Rent::default(),
+            1,
+            1,
+        );
+        assert_eq!(
+            transaction_context.diff(&shorter_transaction_context),
+            Err(InstructionError::NotEnoughAccountKeys),
+        );
+    }
+
+    #[test]
+    fn test_set_account_max_length() {
+        let program_id = Pubkey::new_unique();
+        let mut transaction_context = TransactionContext::new(
+            vec![
+                (program_id, AccountSharedData::default()),
+                (
+                    Pubkey::new_unique(),
+                    AccountSharedData::new(1, 4, &program_id),
+                ),
+            ],
+            Rent::default(),
+            1,
+            1,
+        );
+        transaction_context
+            .get_next_instruction_context_mut()
+            .unwrap()
+            .configure(
+                vec![0],
+                vec![InstructionAccount::new(1, 0, false, true)],
+                &[],
+            );
+        transaction_context.push().unwrap();
+        assert_eq!(
+            transaction_context.set_account_max_length(2, 8),
+            Err(InstructionError::NotEnoughAccountKeys),
+        );
+        // Limits are only allocated once one is set
+        assert!(transaction_context
+            .accounts
+            .max_data_lengths
+            .borrow()
+            .is_none());
+        transaction_context.set_account_max_length(1, 8).unwrap();
+        assert!(transaction_context
+            .accounts
+            .max_data_lengths
+            .borrow()
+            .is_some());
+
+        let instruction_context = transaction_context
+            .get_current_instruction_context()
+            .unwrap();
+        let mut account = instruction_context
+            .try_borrow_instruction_account(&transaction_context, 0)
+            .unwrap();
+        assert_eq!(
+            account.set_data_length(9),
+            Err(InstructionError::InvalidRealloc)
+        );
+        account.set_data_length(8).unwrap();
+        drop(account);
+
+        // Shrinking is allowed even if the account is already longer than its maximum
+        transaction_context.set_account_max_length(1, 2).unwrap();
+        let mut account = instruction_context
+            .try_borrow_instruction_account(&transaction_context, 0)
+            .unwrap();
+        account.set_data_length(7).unwrap();
+        assert_eq!(
+            account.set_data_length(8),
+            Err(InstructionError::InvalidRealloc)
+        );
+        drop(account);
+    }
+
+    #[test]
+    fn test_access_violation_handler_max_data_length() {
+        let transaction_context = TransactionContext::new(
+            vec![
+                (Pubkey::new_unique(), AccountSharedData::default()),
+                (
+                    Pubkey::new_unique(),
+                    AccountSharedData::new(1, 4, &Pubkey::new_unique()),
+                ),
+            ],
+            Rent::default(),
+            1,
+            1,
+        );
+        transaction_context.set_account_max_length(1, 8).unwrap();
+        let access_violation_handler = transaction_context.access_violation_handler();
+        let mut region = MemoryRegion {
+            vm_addr: 0x1000,
+            len: 4,
+            access_violation_handler_payload: Some(1),
+            ..MemoryRegion::default()
+        };
+
+        // Grows up to the maximum data length, not up to the reserved address space
+        access_violation_handler(&mut region, 100, AccessType::Store, 0x1000, 100);
+        assert_eq!(region.len, 8);
+        assert_eq!(
+            transaction_context
+                .accounts
+                .try_borrow(1)
+                .unwrap()
+                .data()
+                .len(),
+            8
+        );
+        assert_eq!(transaction_context.accounts_resize_delta(), Ok(4));
+
+        // Never shrinks an account which is already longer than its maximum data length
+        transaction_context.set_account_max_length(1, 2).unwrap();
+        access_violation_handler(&mut region, 100, AccessType::Store, 0x1000, 100);
+        assert_eq!(region.len, 8);
+        assert_eq!(transaction_context.accounts_resize_delta(), Ok(4));
+    }
+
+    #[test]
+    fn test_can_resize_accounts() {
+        let max_len = MAX_PERMITTED_DATA_LENGTH as usize;
+        let transaction_context = TransactionContext::new(
+            (0..3)
+                .map(|_| {
+                    (
+                        Pubkey::new_unique(),
+                        AccountSharedData::new(1, 10, &Pubkey::new_unique()),
+                    )
+                })
+                .collect(),
+            Rent::default(),
+            1,
+            1,
+        );
+        assert_eq!(transaction_context.can_resize_accounts(&[]), Ok(()));
+        assert_eq!(
+            transaction_context.can_resize_accounts(&[(0, max_len + 1)]),
+            Err(InstructionError::InvalidRealloc),
+        );
+        assert_eq!(
+            transaction_context.can_resize_accounts(&[(3, 0)]),
+            Err(InstructionError::MissingAccount),
+        );
+
+        // The resizes add up across accounts
+        assert_eq!(
+            transaction_context.can_resize_accounts(&[(0, max_len), (1, max_len)]),
+            Ok(()),
+        );
+        assert_eq!(
+            transaction_context.can_resize_accounts(&[(0, max_len), (1, max_len), (2, 64)]),
+            Err(InstructionError::MaxAccountsDataAllocationsExceeded),
+        );
+        // Earlier resizes of the same account are taken into account
+        assert_eq!(
+            transaction_context.can_resize_accounts(&[(0, max_len), (0, 0), (1, max_len), (2, 64)]),
+            Ok(()),
+        );
+        transaction_context.set_account_max_length(2, 16).unwrap();
+        assert_eq!(
+            transaction_context.can_resize_accounts(&[(2, 17)]),
+            Err(InstructionError::InvalidRealloc),
+        );
+
+        // Nothing was actually resized
+        for index_in_transaction in 0..3 {
+            assert_eq!(
+                transaction_context
+                    .accounts
+                    .try_borrow(index_in_transaction)
+                    .unwrap()
+                    .data()
+                    .len(),
+                10
+            );
+        }
+        assert_eq!(transaction_context.accounts_resize_delta(), Ok(0));
+    }
+
+    #[test]
+    fn test_unborrowed_account_indices() {
+        let mut transaction_context = TransactionContext::new(
+            (0..4)
+                .map(|_| (Pubkey::new_unique(), AccountSharedData::default()))
+                .collect(),
+            Rent::default(),
+            1,
+            1,
+        );
+        assert_eq!(
+            transaction_context.unborrowed_account_indices(),
+            Ok(vec![0, 1, 2, 3])
+        );
+        transaction_context
+            .get_next_instruction_context_mut()
+            .unwrap()
+            .configure(
+                vec![0],
+                vec![
+                    InstructionAccount::new(2, 0, false, true),
+                    InstructionAccount::new(3, 1, false, false),
+                ],
+                &[],
+            );
+        transaction_context.push().unwrap();
+        let instruction_context = transaction_context
+            .get_current_instruction_context()
+            .unwrap();
+        instruction_context
+            .try_borrow_program_account(&transaction_context, 0)
+            .unwrap();
+        instruction_context
+            .try_borrow_instruction_account(&transaction_context, 0)
+            .unwrap();
+        // Borrowing outside of an instruction does not count
+        transaction_context
+            .try_borrow_account_mut_by_index(1)
+            .unwrap();
+        assert_eq!(
+            transaction_context.unborrowed_account_indices(),
+            Ok(vec![1, 3])
+        );
+    }
+
+    #[test]
+    fn test_mark_instruction_replayed() {
+        let mut transaction_context = TransactionContext::new(
+            vec![(Pubkey::new_unique(), AccountSharedData::default())],
+            Rent::default(),
+            1,
+            3,
+        );
+        for _ in 0..3 {
+            transaction_context
+                .get_next_instruction_context_mut()
+                .unwrap()
+                .configure(vec![0], vec![], &[]);
+            transaction_context.push().unwrap();
+            transaction_context.pop().unwrap();
+        }
+        transaction_context.mark_instruction_replayed(1);
+        transaction_context.mark_instruction_replayed(1);
+        assert_eq!(
+            (0..4)
+                .map(|index_in_trace| transaction_context.is_instruction_replayed(index_in_trace))
+                .collect::<Vec<_>>(),
+            vec![false, true, false, false],
+        );
+    }
+
+    fn transaction_context_for_balanced_borrow_guard() -> TransactionContext {
+        let program_id = Pubkey::new_unique();
+        let mut transaction_context = TransactionContext::new(
+            std::iter::once((program_id, AccountSharedData::default()))
+                .chain((1..4).map(|_| {
+                    (
+                        Pubkey::new_unique(),
+                        AccountSharedData::new(10, 0, &program_id),
+                    )
+                }))
+                .collect(),
+            Rent::default(),
+            1,
+            1,
+        );
+        transaction_context
+            .get_next_instruction_context_mut()
+            .unwrap()
+            .configure(
+                vec![0],
+                (1..4)
+                    .map(|index_in_transaction| {
+                        InstructionAccount::new(
+                            index_in_transaction,
+                            index_in_transaction.saturating_sub(1),
+                            false,
+                            true,
+                        )
+                    })
+                    .collect(),
+                &[],
+            );
+        transaction_context.push().unwrap();
+        transaction_context
+    }
+
+    #[test]
+    fn test_balanced_borrow_guard() {
+        let transaction_context = transaction_context_for_balanced_borrow_guard();
+        let instruction_context = transaction_context
+            .get_current_instruction_context()
+            .unwrap();
+        assert_eq!(
+            instruction_context
+                .balanced_borrow_guard(&transaction_context, &[0, 3])
+                .err(),
+            Some(InstructionError::NotEnoughAccountKeys),
+        );
+
+        // Moving lamports between the guarded accounts keeps the sum
+        let guard = instruction_context
+            .balanced_borrow_guard(&transaction_context, &[0, 1, 0])
+            .unwrap();
+        let mut account = instruction_context
+            .try_borrow_instruction_account(&transaction_context, 0)
+            .unwrap();
+        account.checked_sub_lamports(3).unwrap();
+        drop(account);
+        let mut account = instruction_context
+            .try_borrow_instruction_account(&transaction_context, 1)
+            .unwrap();
+        account.checked_add_lamports(3).unwrap();
+        drop(account);
+        drop(guard);
+
+        // Moving lamports out of the guarded accounts has to be declared
+        let mut guard = instruction_context
+            .balanced_borrow_guard(&transaction_context, &[0])
+            .unwrap();
+        let mut account = instruction_context
+            .try_borrow_instruction_account(&transaction_context, 0)
+            .unwrap();
+        account.checked_sub_lamports(4).unwrap();
+        drop(account);
+        let mut account = instruction_context
+            .try_borrow_instruction_account(&transaction_context, 2)
+            .unwrap();
+        account.checked_add_lamports(4).unwrap();
+        drop(account);
+        guard.commit_delta(-4);
+        drop(guard);
+
+        // The check is skipped while an account is still borrowed
+        let guard = instruction_context
+            .balanced_borrow_guard(&transaction_context, &[0])
+            .unwrap();
+        let mut account = instruction_context
+            .try_borrow_instruction_account(&transaction_context, 0)
+            .unwrap();
+        account.checked_add_lamports(1).unwrap();
+        drop(guard);
+        account.checked_sub_lamports(1).unwrap();
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    #[should_panic(expected = "lamport sum of accounts [1] changed")]
+    fn test_balanced_borrow_guard_unbalanced() {
+        let transaction_context = transaction_context_for_balanced_borrow_guard();
+        let instruction_context = transaction_context
+            .get_current_instruction_context()
+            .unwrap();
+        let _guard = instruction_context
+            .balanced_borrow_guard(&transaction_context, &[0])
+            .unwrap();
+        let mut account = instruction_context
+            .try_borrow_instruction_account(&transaction_context, 0)
+            .unwrap();
+        account.checked_add_lamports(1).unwrap();
+    }
+
+    #[test]
+    fn test_lamport_reservation() {
+        let program_id = Pubkey::new_unique();
+        let mut transaction_context = TransactionContext::new(
+            vec![
+                (program_id, AccountSharedData::default()),
+                (
+                    Pubkey::new_unique(),
+                    AccountSharedData::new(10, 0, &program_id),
+                ),
+                (
+                    Pubkey::new_unique(),
+                    AccountSharedData::new(10, 0, &Pubkey::new_unique()),
+                ),
+            ],
+            Rent::default(),
+            1,
+            1,
+        );
+        transaction_context
+            .get_next_instruction_context_mut()
+            .unwrap()
+            .configure(
+                vec![0],
+                vec![
+                    InstructionAccount::new(1, 0, false, true),
+                    InstructionAccount::new(2, 1, false, true),
+                ],
+                &[],
+            );
+        transaction_context.push().unwrap();
+        let instruction_context = transaction_context
+            .get_current_instruction_context()
+            .unwrap();
+
+        let mut account = instruction_context
+            .try_borrow_instruction_account(&transaction_context, 0)
+            .unwrap();
+        {
+            let mut reservation = LamportReservation::new(&mut account);
+            assert_eq!(reservation.available(), 10);
+            reservation.reserve(3).unwrap();
+            reservation.reserve(4).unwrap();
+            assert_eq!(
+                reservation.reserve(4),
+                Err(InstructionError::InsufficientFunds)
+            );
+            assert_eq!(reservation.available(), 3);
+        }
+        // Nothing is deducted without a commit
+        assert_eq!(account.get_lamports(), 10);
+
+        let mut reservation = LamportReservation::new(&mut account);
+        reservation.reserve(7).unwrap();
+        reservation.commit().unwrap();
+        assert_eq!(account.get_lamports(), 3);
+        drop(account);
+
+        let mut account = instruction_context
+            .try_borrow_instruction_account(&transaction_context, 1)
+            .unwrap();
+        let mut reservation = LamportReservation::new(&mut account);
+        reservation.reserve(1).unwrap();
+        assert_eq!(
+            reservation.commit(),
+            Err(InstructionError::ExternalAccountLamportSpend)
+        );
+        assert_eq!(account.get_lamports(), 10);
+    }
+
+    #[test]
+    fn test_initialize() {
+        let program_id = Pubkey::new_unique();
+        let new_owner = Pubkey::new_unique();
+        let mut transaction_context = TransactionContext::new(
+            vec![
+                (program_id, AccountSharedData::default()),
+                (
+                    Pubkey::new_unique(),
+                    AccountSharedData::new(0, 0, &program_id),
+                ),
+                (
+                    Pubkey::new_unique(),
+                    AccountSharedData::new(1, 0, &program_id),
+                ),
+                (
+                    Pubkey::new_unique(),
+                    AccountSharedData::new(0, 0, &program_id),
+                ),
+            ],
+            Rent::default(),
+            1,
+            1,
+        );
+        transaction_context
+            .get_next_instruction_context_mut()
+            .unwrap()
+            .configure(
+                vec![0],
+                vec![
+                    InstructionAccount::new(1, 0, false, true),
+                    InstructionAccount::new(2, 1, false, true),
+                    InstructionAccount::new(3, 2, false, false),
+                ],
+                &[],
+            );
+        transaction_context.push().unwrap();
+        let instruction_context = transaction_context
+            .get_current_instruction_context()
+            .unwrap();
+        let assert_unchanged = |account: &BorrowedAccount| {
+            assert_eq!(account.get_data(), &[] as &[u8]);
+            assert_eq!(account.get_owner(), &program_id);
+        };
+
+        let mut account = instruction_context
+            .try_borrow_instruction_account(&transaction_context, 0)
+            .unwrap();
+        assert_eq!(
+            account.initialize(&[0; 31], 2, 3),
+            Err(InstructionError::InvalidArgument),
+        );
+        assert_unchanged(&account);
+        assert_eq!(
+            account.initialize(
+                new_owner.as_ref(),
+                2,
+                MAX_PERMITTED_DATA_LENGTH as usize + 1
+            ),
+            Err(InstructionError::InvalidRealloc),
+        );
+        assert_unchanged(&account);
+        assert_eq!(account.get_lamports(), 0);
+        assert!(!transaction_context.is_account_touched(1).unwrap());
+        account.initialize(new_owner.as_ref(), 2, 3).unwrap();
+        assert_eq!(account.get_lamports(), 2);
+        assert_eq!(account.get_data(), &[0; 3]);
+        assert_eq!(account.get_owner(), &new_owner);
+        drop(account);
+
+        let mut account = instruction_context
+            .try_borrow_instruction_account(&transaction_context, 1)
+            .unwrap();
+        assert_eq!(
+            account.initialize(new_owner.as_ref(), 2, 3),
+            Err(InstructionError::AccountAlreadyInitialized),
+        );
+        assert_unchanged(&account);
+        assert_eq!(account.get_lamports(), 1);
+        drop(account);
+
+        let mut account = instruction_context
+            .try_borrow_instruction_account(&transaction_context, 2)
+            .unwrap();
+        assert_eq!(
+            account.initialize(new_owner.as_ref(), 2, 3),
+            Err(InstructionError::ReadonlyDataModified),
+        );
+        assert_unchanged(&account);
+        assert_eq!(account.get_lamports(), 0);
+    }
+
+    #[test]
+    fn test_stack_observer() {
+        let program_a = Pubkey::new_unique();
+        let program_b = Pubkey::new_unique();
+        let mut transaction_context = TransactionContext::new(
+            vec![
+                (program_a, AccountSharedData::default()),
+                (program_b, AccountSharedData::default()),
+            ],
+            Rent::default(),
+            2,
+            4,
+        );
+        let events = Rc::new(RefCell::new(Vec::new()));
+        let recorded_events = Rc::clone(&events);
+        transaction_context.set_stack_observer(Box::new(move |event| {
+            recorded_events.borrow_mut().push(event);
+        }));
+
+        assert_eq!(transaction_context.pop(), Err(InstructionError::CallDepth));
+        // The third push exceeds the stack capacity and is not reported
+        for (program_account, expected_result) in [
+            (0, Ok(())),
+            (1, Ok(())),
+            (0, Err(InstructionError::CallDepth)),
+        ] {
+            transaction_context
+                .get_next_instruction_context_mut()
+                .unwrap()
+                .configure(vec![program_account], vec![], &[]);
+            assert_eq!(transaction_context.push(), expected_result);
+        }
+        transaction_context.pop().unwrap();
+        transaction_context.pop().unwrap();
+
+        assert_eq!(
+            *events.borrow(),
+            vec![
+                StackEvent::Push {
+                    nesting_level: 0,
+                    program_key: Some(program_a),
+                },
+                StackEvent::Push {
+                    nesting_level: 1,
+                    program_key: Some(program_b),
+                },
+                StackEvent::Pop {
+                    nesting_level: 1,
+                    program_key: Some(program_b),
+                },
+                StackEvent::Pop {
+                    nesting_level: 0,
+                    program_key: Some(program_a),
+                },
+            ],
+        );
+    }
+
+    #[test]
+    fn test_trace_sink() {
+        let program_id = Pubkey::new_unique();
+        let mut transaction_context = TransactionContext::new(
+            vec![
+                (program_id, AccountSharedData::default()),
+                (
+                    Pubkey::new_unique(),
+                    AccountSharedData::new(1, 0, &program_id),
+                ),
+            ],
+            Rent::default(),
+            2,
+            4,
+        );
+        let popped_instruction_data = Rc::new(RefCell::new(Vec::new()));
+        let recorded_instruction_data = Rc::clone(&popped_instruction_data);
+        transaction_context.set_trace_sink(Box::new(move |instruction_context| {
+            recorded_instruction_data
+                .borrow_mut()
+                .push(instruction_context.get_instruction_data().to_vec());
+        }));
+
+        for instruction_data in [[1], [2]] {
+            transaction_context
+                .get_next_instruction_context_mut()
+                .unwrap()
+                .configure(
+                    vec![0],
+                    vec![InstructionAccount::new(1, 0, false, true)],
+                    &instruction_data,
+                );
+            transaction_context.push().unwrap();
+        }
+        transaction_context.pop().unwrap();
+        // Unbalanced instructions are passed to the sink as well
+        transaction_context
+            .get_current_instruction_context()
+            .unwrap()
+            .try_borrow_instruction_account(&transaction_context, 0)
+            .unwrap()
+            .checked_add_lamports(1)
+            .unwrap();
+        assert_eq!(
+            transaction_context.pop(),
+            Err(InstructionError::UnbalancedInstruction)
+        );
+
+        assert_eq!(*popped_instruction_data.borrow(), vec![vec![2], vec![1]]);
+        // The instruction trace is recorded as usual
+        assert_eq!(transaction_context.get_instruction_trace_length(), 2);
+    }
+
+    #[test]
+    fn test_peak_total_data_len() {
+        let program_id = Pubkey::new_unique();
+        let mut transaction_context = TransactionContext::new(
+            vec![
+                (program_id, AccountSharedData::default()),
+                (
+                    Pubkey::new_unique(),
+                    AccountSharedData::new(1, 10, &program_id),
+                ),
+                (
+                    Pubkey::new_unique(),
+                    AccountSharedData::new(1, 20, &program_id),
+                ),
+            ],
+            Rent::default(),
+            1,
+            1,
+        );
+        transaction_context
+            .get_next_instruction_context_mut()
+            .unwrap()
+            .configure(
+                vec![0],
+                vec![
+                    InstructionAccount::new(1, 0, false, true),
+                    InstructionAccount::new(2, 1, false, true),
+                ],
+                &[],
+            );
+        transaction_context.push().unwrap();
+        assert_eq!(transaction_context.peak_total_data_len(), 30);
+        let instruction_context = transaction_context
+            .get_current_instruction_context()
+            .unwrap();
+
+        // Shrinking below the original total length does not lower the peak
+        let mut account = instruction_context
+            .try_borrow_instruction_account(&transaction_context, 1)
+            .unwrap();
+        account.set_data_length(5).unwrap();
+        drop(account);
+        assert_eq!(transaction_context.peak_total_data_len(), 30);
+
+        let mut account = instruction_context
+            .try_borrow_instruction_account(&transaction_context, 0)
+            .unwrap();
+        account.set_data_length(110).unwrap();
+        assert_eq!(transaction_context.peak_total_data_len(), 115);
+        account.set_data_length(0).unwrap();
+        drop(account);
+        let mut account = instruction_context
+            .try_borrow_instruction_account(&transaction_context, 1)
+            .unwrap();
+        account.set_data_length(25).unwrap();
+        drop(account);
+        assert_eq!(transaction_context.accounts_resize_delta(), Ok(-5));
+        assert_eq!(transaction_context.peak_total_data_len(), 115);
+    }
+
+    #[test]
+    fn test_copy_account_data() {
+        let program_id = Pubkey::new_unique();
+        let mut transaction_context = TransactionContext::new(
+            vec![
+                (program_id, AccountSharedData::default()),
+                (
+                    Pubkey::new_unique(),
+                    AccountSharedData::create(1, vec![1, 2, 3], program_id, false, 0),
+                ),
+                (
+                    Pubkey::new_unique(),
+                    AccountSharedData::new(1, 5, &program_id),
+                ),
+                (
+                    Pubkey::new_unique(),
+                    AccountSharedData::new(1, 5, &program_id),
+                ),
+            ],
+            Rent::default(),
+            1,
+            1,
+        );
+        assert_eq!(
+            transaction_context.copy_account_data(0, 1),
+            Err(InstructionError::CallDepth),
+        );
+        transaction_context
+            .get_next_instruction_context_mut()
+            .unwrap()
+            .configure(
+                vec![0],
+                vec![
+                    InstructionAccount::new(1, 0, false, false),
+                    InstructionAccount::new(2, 1, false, true),
+                    InstructionAccount::new(3, 2, false, false),
+                    InstructionAccount::new(1, 0, false, false),
+                ],
+                &[],
+            );
+        transaction_context.push().unwrap();
+        let get_data = |index_in_transaction| {
+            transaction_context
+                .accounts
+                .try_borrow(index_in_transaction)
+                .unwrap()
+                .data()
+                .to_vec()
+        };
+
+        assert_eq!(
+            transaction_context.copy_account_data(0, 4),
+            Err(InstructionError::NotEnoughAccountKeys),
+        );
+        // Copying an account onto itself does not require it to be writable
+        assert_eq!(transaction_context.copy_account_data(0, 3), Ok(()));
+        assert_eq!(
+            transaction_context.copy_account_data(0, 2),
+            Err(InstructionError::ReadonlyDataModified),
+        );
+        assert_eq!(get_data(3), vec![0; 5]);
+
+        transaction_context.copy_account_data(0, 1).unwrap();
+        assert_eq!(get_data(2), vec![1, 2, 3]);
+        assert_eq!(get_data(1), vec![1, 2, 3]);
+        assert_eq!(transaction_context.accounts_resize_delta(), Ok(-2));
+    }
+
+    #[test]
+    fn test_validate_trace() {
+        let mut transaction_context = TransactionContext::new(
+            vec![(Pubkey::new_unique(), AccountSharedData::default())],
+            Rent::default(),
+            2,
+            3,
+        );
+        assert_eq!(transaction_context.validate_trace(), Ok(()));
+        for _ in 0..2 {
+            transaction_context
+                .get_next_instruction_context_mut()
+                .unwrap()
+                .configure(vec![0], vec![], &[]);
+            transaction_context.push().unwrap();
+        }
+        transaction_context
+            .get_next_instruction_context_mut()
+            .unwrap()
+            .configure(vec![0], vec![], &[]);
+        assert_eq!(transaction_context.validate_trace(), Ok(()));
+
+        let mut invalid = transaction_context.clone();
+        invalid.instruction_trace.clear();
+        assert_eq!(
+            invalid.validate_trace(),
+            Err(InstructionError::GenericError)
+        );
+
+        let mut invalid = transaction_context.clone();
+        invalid
+            .instruction_trace
+            .extend([InstructionContext::default(), InstructionContext::default()]);
+        assert_eq!(
+            invalid.validate_trace(),
+            Err(InstructionError::MaxInstructionTraceLengthExceeded),
+        );
+
+        // Exceeds the stack capacity
+        let mut invalid = transaction_context.clone();
+        invalid.instruction_stack.push(2);
+        assert_eq!(invalid.validate_trace(), Err(InstructionError::CallDepth));
+
+        // Points to the pre-reserved InstructionContext
+        let mut invalid = transaction_context.clone();
+        invalid.instruction_stack.pop();
+        invalid.instruction_stack.push(2);
+        assert_eq!(invalid.validate_trace(), Err(InstructionError::CallDepth));
+
+        // Not in execution order
+        let mut invalid = transaction_context.clone();
+        invalid.instruction_stack.reverse();
+        assert_eq!(invalid.validate_trace(), Err(InstructionError::CallDepth));
+
+        // Nesting level does not match the position in the stack
+        let mut invalid = transaction_context;
+        invalid.instruction_stack.remove(0);
+        assert_eq!(invalid.validate_trace(), Err(InstructionError::CallDepth));
+    }
+
+    #[test]
+    fn test_try_dump_accounts() {
+        let key_0 = Pubkey::new_unique();
+        let key_1 = Pubkey::new_unique();
+        let owner = Pubkey::new_unique();
+        let transaction_context = TransactionContext::new(
+            vec![
+                (key_0, AccountSharedData::new(1, 2, &owner)),
+                (key_1, AccountSharedData::default()),
+            ],
+            Rent::default(),
+            1,
+            1,
+        );
+        assert_eq!(
+            transaction_context.try_dump_accounts(),
+            Ok(vec![
+                (
+                    key_0,
+                    AccountSummary {
+                        lamports: 1,
+                        owner,
+                        data_len: 2,
+                    },
+                ),
+                (
+                    key_1,
+                    AccountSummary {
+                        lamports: 0,
+                        owner: Pubkey::default(),
+                        data_len: 0,
+                    },
+                ),
+            ]),
+        );
+
+        let borrowed_account = transaction_context
+            .try_borrow_account_mut_by_index(1)
+            .unwrap();
+        assert_eq!(
+            transaction_context.try_dump_accounts(),
+            Err(InstructionError::AccountBorrowOutstanding),
+        );
+        drop(borrowed_account);
+    }
+
+    #[test]
+    #[cfg(feature = "bytemuck")]
+    fn test_get_record_slice() {
+        let data = [7u32, 1, 2]
+            .iter()
+            .flat_map(|record| record.to_ne_bytes())
+            .collect::<Vec<_>>();
+        let transaction_context = TransactionContext::new(
+            vec![(
+                Pubkey::new_unique(),
+                AccountSharedData::create(1, data, Pubkey::new_unique(), false, 0),
+            )],
+            Rent::default(),
+            1,
+            1,
+        );
+        let instruction_context = InstructionContext::new_for_tests(0, vec![0], vec![], vec![]);
+        let account = instruction_context
+            .try_borrow_program_account(&transaction_context, 0)
+            .unwrap();
+        assert_eq!(
+            account.get_record_slice::<u32>(4, 2),
+            Ok([1u32, 2].as_slice())
+        );
+        assert_eq!(account.get_record_slice::<u32>(12, 0), Ok([].as_slice()));
+        // Does not fit
+        assert_eq!(
+            account.get_record_slice::<u32>(4, 3),
+            Err(InstructionError::InvalidAccountData),
+        );
+        assert_eq!(
+            account.get_record_slice::<u32>(4, usize::MAX),
+            Err(InstructionError::InvalidAccountData),
+        );
+        // Not aligned
+        assert_eq!(
+            account.get_record_slice::<u32>(1, 2),
+            Err(InstructionError::InvalidAccountData),
+        );
+    }
+
+    #[test]
+    fn test_try_borrow_instructions_sysvar() {
+        let build_transaction_context = |transaction_accounts| {
+            TransactionContext::new(transaction_accounts, Rent::default(), 1, 1)
+        };
+        assert_eq!(
+            build_transaction_context(vec![(Pubkey::new_unique(), AccountSharedData::default())])
+                .try_borrow_instructions_sysvar()
+                .err(),
+            Some(InstructionError::MissingAccount),
+        );
+        assert_eq!(
+            build_transaction_context(vec![(
+                instructions::id(),
+                AccountSharedData::new(1, 2, &Pubkey::new_unique()),
+            )])
+            .try_borrow_instructions_sysvar()
+            .err(),
+            Some(InstructionError::InvalidAccountOwner),
+        );
+
+        let transaction_context = build_transaction_context(vec![
+            (Pubkey::new_unique(), AccountSharedData::default()),
+            (
+                instructions::id(),
+                AccountSharedData::new(1, 2, &solana_sdk_ids::sysvar::id()),
+            ),
+        ]);
+        let borrowed_account = transaction_context
+            .try_borrow_account_mut_by_index(1)
+            .unwrap();
+        assert_eq!(
+            transaction_context.try_borrow_instructions_sysvar().err(),
+            Some(InstructionError::AccountBorrowFailed),
+        );
+        drop(borrowed_account);
+        let account = transaction_context
+            .try_borrow_instructions_sysvar()
+            .unwrap();
+        assert_eq!(account.data(), &[0, 0]);
+        // The borrow is read-only, so it can be shared
+        assert!(transaction_context.try_borrow_instructions_sysvar().is_ok());
+    }
+
+    #[test]
+    fn test_accounts_equal() {
+        let transaction_accounts = (0..2)
+            .map(|_| {
+                (
+                    Pubkey::new_unique(),
+                    AccountSharedData::new(1, 2, &Pubkey::new_unique()),
+                )
+            })
+            .collect::<Vec<_>>();
+        let transaction_context =
+            TransactionContext::new(transaction_accounts.clone(), Rent::default(), 1, 1);
+        let other_transaction_context =
+            TransactionContext::new(transaction_accounts.clone(), Rent::default(), 1, 1);
+        assert_eq!(
+            transaction_context.accounts_equal(&other_transaction_context),
+            Ok(true)
+        );
+
+        other_transaction_context
+            .try_borrow_account_mut_by_index(1)
+            .unwrap()
+            .set_lamports(2);
+        assert_eq!(
+            transaction_context.accounts_equal(&other_transaction_context),
+            Ok(false)
+        );
+
+        let mut other_transaction_accounts = transaction_accounts.clone();
+        other_transaction_accounts.reverse();
+        let other_transaction_context =
+            TransactionContext::new(other_transaction_accounts, Rent::default(), 1, 1);
+        assert_eq!(
+            transaction_context.accounts_equal(&other_transaction_context),
+            Ok(false)
+        );
+
+        let other_transaction_context = TransactionContext::new(
+            transaction_accounts.into_iter().take(1).collect(),
+            Rent::default(),
+            1,
+            1,
+        );
+        assert_eq!(
+            transaction_context.accounts_equal(&other_transaction_context),
+            Ok(false)
+        );
+
+        let borrowed_account = transaction_context
+            .try_borrow_account_mut_by_index(0)
+            .unwrap();
+        assert_eq!(
+            transaction_context.accounts_equal(&transaction_context.clone()),
+            Err(InstructionError::AccountBorrowFailed),
+        );
+        drop(borrowed_account);
+    }
+
+    #[test]
+    fn test_recorded_lamport_sum() {
+        let mut transaction_context = TransactionContext::new(
+            vec![
+                (Pubkey::new_unique(), AccountSharedData::default()),
+                (
+                    Pubkey::new_unique(),
+                    AccountSharedData::new(3, 0, &Pubkey::new_unique()),
+                ),
+                (
+                    Pubkey::new_unique(),
+                    AccountSharedData::new(4, 0, &Pubkey::new_unique()),
+                ),
+            ],
+            Rent::default(),
+            1,
+            1,
+        );
+        transaction_context
+            .get_next_instruction_context_mut()
+            .unwrap()
+            .configure(
+                vec![0],
+                vec![
+                    InstructionAccount::new(1, 0, false, false),
+                    InstructionAccount::new(2, 1, false, false),
+                    InstructionAccount::new(1, 0, false, false),
+                ],
+                &[],
+            );
+        assert_eq!(
+            transaction_context
+                .get_next_instruction_context()
+                .unwrap()
+                .recorded_lamport_sum(),
+            0
+        );
+        transaction_context.push().unwrap();
+        // Duplicate instruction accounts are only counted once
+        assert_eq!(
+            transaction_context
+                .get_current_instruction_context()
+                .unwrap()
+                .recorded_lamport_sum(),
+            7
+        );
+    }
+
+    #[test]
+    fn test_get_first_program_key() {
+        let loader_id = Pubkey::new_unique();
+        let program_id = Pubkey::new_unique();
+        let transaction_context = TransactionContext::new(
+            vec![
+                (program_id, AccountSharedData::default()),
+                (loader_id, AccountSharedData::default()),
+            ],
+            Rent::default(),
+            1,
+            1,
+        );
+        let instruction_context = InstructionContext::new_for_tests(0, vec![1, 0], vec![], vec![]);
+        assert_eq!(
+            instruction_context.get_first_program_key(&transaction_context),
+            Ok(&loader_id)
+        );
+        assert_eq!(
+            instruction_context.get_last_program_key(&transaction_context),
+            Ok(&program_id)
+        );
+
+        let instruction_context = InstructionContext::new_for_tests(0, vec![], vec![], vec![]);
+        assert_eq!(
+            instruction_context.get_first_program_key(&transaction_context),
+            Err(InstructionError::NotEnoughAccountKeys),
+        );
+    }
+
+    #[test]
+    fn test_set_instruction_data() {
+        let mut transaction_context = TransactionContext::new(
+            vec![(Pubkey::new_unique(), AccountSharedData::default())],
+            Rent::default(),
+            1,
+            1,
+        );
+        let instruction_context = transaction_context
+            .get_next_instruction_context_mut()
+            .unwrap();
+        instruction_context.configure(vec![0], vec![], &[1, 2]);
+        instruction_context.set_instruction_data(vec![3, 4, 5]);
+        transaction_context.push().unwrap();
+        assert_eq!(
+            transaction_context
+                .get_current_instruction_context()
+                .unwrap()
+                .get_instruction_data(),
+            &[3, 4, 5]
+        );
+    }
+
+    #[test]
+    fn test_key_owner_pairs() {
+        let key = Pubkey::new_unique();
+        let owner = Pubkey::new_unique();
+        let transaction_context = TransactionContext::new(
+            vec![
+                (key, AccountSharedData::new(1, 0, &owner)),
+                (key, AccountSharedData::default()),
+            ],
+            Rent::default(),
+            1,
+            1,
+        );
+        assert_eq!(
+            transaction_context.key_owner_pairs(),
+            Ok(vec![(key, owner), (key, Pubkey::default())])
+        );
+
+        let borrowed_account = transaction_context
+            .try_borrow_account_mut_by_index(1)
+            .unwrap();
+        assert_eq!(
+            transaction_context.key_owner_pairs(),
+            Err(InstructionError::AccountBorrowFailed),
+        );
+        drop(borrowed_account);
+    }
+
+    #[test]
+    fn test_get_data_suffix() {
+        let transaction_context = TransactionContext::new(
+            vec![(
+                Pubkey::new_unique(),
+                AccountSharedData::create(1, vec![1, 2, 3], Pubkey::new_unique(), false, 0),
+            )],
+            Rent::default(),
+            1,
+            1,
+        );
+        let instruction_context = InstructionContext::new_for_tests(0, vec![0], vec![], vec![]);
+        let account = instruction_context
+            .try_borrow_program_account(&transaction_context, 0)
+            .unwrap();
+        assert_eq!(account.get_data_suffix(0), Ok([].as_slice()));
+        assert_eq!(account.get_data_suffix(2), Ok([2, 3].as_slice()));
+        assert_eq!(account.get_data_suffix(3), Ok([1, 2, 3].as_slice()));
+        assert_eq!(
+            account.get_data_suffix(4),
+            Err(InstructionError::AccountDataTooSmall)
+        );
+    }
+
+    #[test]
+    fn test_is_executable_effective() {
+        let mut transaction_context = TransactionContext::new(
+            vec![(
+                Pubkey::new_unique(),
+                AccountSharedData::create(1, vec![], Pubkey::new_unique(), true, 0),
+            )],
+            Rent::default(),
+            1,
+            1,
+        );
+        let instruction_context = InstructionContext::new_for_tests(0, vec![0], vec![], vec![]);
+        for (remove_accounts_executable_flag_checks, is_executable_effective) in
+            [(true, false), (false, true)]
+        {
+            transaction_context
+                .set_remove_accounts_executable_flag_checks(remove_accounts_executable_flag_checks);
+            let account = instruction_context
+                .try_borrow_program_account(&transaction_context, 0)
+                .unwrap();
+            assert_eq!(account.is_executable_effective(), is_executable_effective);
+        }
+    }
+
+    #[test]
+    fn test_account_size_delta() {
+        let program_id = Pubkey::new_unique();
+        let mut transaction_context = TransactionContext::new(
+            vec![
+                (program_id, AccountSharedData::default()),
+                (
+                    Pubkey::new_unique(),
+                    AccountSharedData::new(1, 10, &program_id),
+                ),
+            ],
+            Rent::default(),
+            1,
+            1,
+        );
+        transaction_context
+            .get_next_instruction_context_mut()
+            .unwrap()
+            .configure(
+                vec![0],
+                vec![InstructionAccount::new(1, 0, false, true)],
+                &[],
+            );
+        transaction_context.push().unwrap();
+        assert_eq!(transaction_context.account_size_delta(1), Ok(0));
+        assert_eq!(
+            transaction_context.account_size_delta(2),
+            Err(InstructionError::NotEnoughAccountKeys),
+        );
+
+        let instruction_context = transaction_context
+            .get_current_instruction_context()
+            .unwrap();
+        let mut account = instruction_context
+            .try_borrow_instruction_account(&transaction_context, 0)
+            .unwrap();
+        account.set_data_length(14).unwrap();
+        assert_eq!(
+            transaction_context.account_size_delta(1),
+            Err(InstructionError::AccountBorrowFailed),
+        );
+        account.set_data_length(6).unwrap();
+        drop(account);
+        assert_eq!(transaction_context.account_size_delta(0), Ok(0));
+        assert_eq!(transaction_context.account_size_delta(1), Ok(-4));
+    }
+
+    #[test]
+    fn test_can_accept_next_instruction() {
+        let mut transaction_context = TransactionContext::new(
+            vec![(Pubkey::new_unique(), AccountSharedData::default())],
+            Rent::default(),
+            2,
+            3,
+        );
+        let push = |transaction_context: &mut TransactionContext| {
+            transaction_context
+                .get_next_instruction_context_mut()
+                .unwrap()
+                .configure(vec![0], vec![], &[]);
+            transaction_context.push()
+        };
+        assert_eq!(transaction_context.remaining_stack_depth(), 2);
+        assert_eq!(transaction_context.remaining_trace_capacity(), 3);
+        assert_eq!(transaction_context.can_accept_next_instruction(), Ok(()));
+
+        push(&mut transaction_context).unwrap();
+        push(&mut transaction_context).unwrap();
+        assert_eq!(transaction_context.remaining_stack_depth(), 0);
+        assert_eq!(transaction_context.remaining_trace_capacity(), 1);
+        assert_eq!(
+            transaction_context.can_accept_next_instruction(),
+            Err(InstructionError::CallDepth),
+        );
+        assert_eq!(
+            push(&mut transaction_context.clone()),
+            Err(InstructionError::CallDepth)
+        );
+
+        transaction_context.pop().unwrap();
+        assert_eq!(transaction_context.can_accept_next_instruction(), Ok(()));
+        push(&mut transaction_context).unwrap();
+        transaction_context.pop().unwrap();
+        assert_eq!(transaction_context.remaining_stack_depth(), 1);
+        assert_eq!(transaction_context.remaining_trace_capacity(), 0);
+        assert_eq!(
+            transaction_context.can_accept_next_instruction(),
+            Err(InstructionError::MaxInstructionTraceLengthExceeded),
+        );
+        assert_eq!(
+            push(&mut transaction_context),
+            Err(InstructionError::MaxInstructionTraceLengthExceeded),
+        );
+    }
+
+    #[test]
+    fn test_validate_against() {
+        let transaction_context = TransactionContext::new(
+            vec![
+                (Pubkey::new_unique(), AccountSharedData::default()),
+                (Pubkey::new_unique(), AccountSharedData::default()),
+            ],
+            Rent::default(),
+            1,
+            1,
+        );
+        let instruction_context = InstructionContext::new_for_tests(
+            0,
+            vec![0],
+            vec![InstructionAccount::new(1, 0, false, false)],
+            vec![],
+        );
+        assert_eq!(
+            instruction_context.validate_against(&transaction_context),
+            Ok(())
+        );
+
+        let instruction_context = InstructionContext::new_for_tests(0, vec![2], vec![], vec![]);
+        assert_eq!(
+            instruction_context.validate_against(&transaction_context),
+            Err(InstructionError::NotEnoughAccountKeys),
+        );
+        let instruction_context = InstructionContext::new_for_tests(
+            0,
+            vec![0],
+            vec![InstructionAccount::new(2, 0, false, false)],
+            vec![],
+        );
+        assert_eq!(
+            instruction_context.validate_against(&transaction_context),
+            Err(InstructionError::NotEnoughAccountKeys),
+        );
+    }
+
+    #[test]
+    fn test_instruction_accounts_by_transaction_index() {
+        let instruction_context = InstructionContext::new_for_tests(
+            0,
+            vec![0],
+            vec![
+                InstructionAccount::new(3, 0, false, false),
+                InstructionAccount::new(1, 1, false, false),
+                InstructionAccount::new(3, 0, false, false),
+                InstructionAccount::new(2, 3, false, false),
+            ],
+            vec![],
+        );
+        assert_eq!(
+            instruction_context.instruction_accounts_by_transaction_index(),
+            vec![1, 3, 0, 2]
+        );
+        assert!(
+            InstructionContext::new_for_tests(0, vec![0], vec![], vec![])
+                .instruction_accounts_by_transaction_index()
+                .is_empty()
+        );
+    }
+
+    #[test]
+    fn test_count_shared_accounts() {
+        let program_id = Pubkey::new_unique();
+        let shared_account = AccountSharedData::create(1, vec![1, 2, 3], program_id, false, 0);
+        let mut transaction_context = TransactionContext::new(
+            vec![
+                (program_id, AccountSharedData::default()),
+                (Pubkey::new_unique(), shared_account.clone()),
+                (Pubkey::new_unique(), shared_account.clone()),
+            ],
+            Rent::default(),
+            1,
+            1,
+        );
+        assert_eq!(transaction_context.count_shared_accounts(), Ok((2, 1)));
+
+        transaction_context
+            .get_next_instruction_context_mut()
+            .unwrap()
+            .configure(
+                vec![0],
+                vec![InstructionAccount::new(1, 0, false, true)],
+                &[],
+            );
+        transaction_context.push().unwrap();
+        let instruction_context = transaction_context
+            .get_current_instruction_context()
+            .unwrap();
+        let mut account = instruction_context
+            .try_borrow_instruction_account(&transaction_context, 0)
+            .unwrap();
+        account.get_data_mut().unwrap();
+        drop(account);
+        assert_eq!(transaction_context.count_shared_accounts(), Ok((1, 2)));
+    }
+
+    #[test]
+    fn test_find_duplicate_instructions() {
+        let mut transaction_context = TransactionContext::new(
+            vec![
+                (Pubkey::new_unique(), AccountSharedData::default()),
+                (Pubkey::new_unique(), AccountSharedData::default()),
+            ],
+            Rent::default(),
+            1,
+            5,
+        );
+        let push = |transaction_context: &mut TransactionContext,
+                    program_account: IndexOfAccount,
+                    instruction_data: &[u8]| {
+            transaction_context
+                .get_next_instruction_context_mut()
+                .unwrap()
+                .configure(vec![program_account], vec![], instruction_data);
+            transaction_context.push().unwrap();
+            transaction_context.pop().unwrap();
+        };
+        push(&mut transaction_context, 0, &[1]);
+        push(&mut transaction_context, 1, &[1]);
+        push(&mut transaction_context, 0, &[2]);
+        push(&mut transaction_context, 0, &[1]);
+        push(&mut transaction_context, 1, &[1]);
+
+        assert_eq!(
+            transaction_context.find_duplicate_instructions(),
+            vec![(0, 3), (1, 4)],
+        );
+        let instruction_context = transaction_context
+            .get_instruction_context_at_index_in_trace(2)
+            .unwrap();
+        assert!(instruction_context.instruction_data_eq(&[2]));
+        assert!(!instruction_context.instruction_data_eq(&[1]));
+    }
+
+    #[test]
+    fn test_peek_account() {
+        let transaction_context = TransactionContext::new(
+            vec![(
+                Pubkey::new_unique(),
+                AccountSharedData::new(42, 0, &Pubkey::new_unique()),
+            )],
+            Rent::default(),
+            1,
+            1,
+        );
+        let account = transaction_context.peek_account(0).unwrap();
+        assert_eq!(account.lamports(), 42);
+        assert_eq!(transaction_context.peek_account(0).unwrap().lamports(), 42);
+        drop(account);
+        assert_eq!(
+            transaction_context.peek_account(1).err(),
+            Some(InstructionError::NotEnoughAccountKeys),
+        );
+    }
+
+    #[test]
+    fn test_uninitialized_instruction_accounts() {
+        let program_id = Pubkey::new_unique();
+        let mut transaction_context = TransactionContext::new(
+            vec![
+                (program_id, AccountSharedData::default()),
+                (Pubkey::new_unique(), AccountSharedData::default()),
+                (
+                    Pubkey::new_unique(),
+                    AccountSharedData::new(1, 0, &program_id),
+                ),
+                (
+                    Pubkey::new_unique(),
+                    AccountSharedData::new(0, 1, &program_id),
+                ),
+            ],
+            Rent::default(),
+            1,
+            1,
+        );
+        transaction_context
+            .get_next_instruction_context_mut()
+            .unwrap()
+            .configure(
+                vec![0],
+                vec![
+                    InstructionAccount::new(3, 0, false, false),
+                    InstructionAccount::new(1, 1, false, false),
+                    InstructionAccount::new(2, 2, false, false),
+                ],
+                &[],
+            );
+        transaction_context.push().unwrap();
+        let instruction_context = transaction_context
+            .get_current_instruction_context()
+            .unwrap();
+        assert_eq!(
+            instruction_context.uninitialized_instruction_accounts(&transaction_context),
+            Ok(vec![1]),
+        );
+        let account = instruction_context
+            .try_borrow_instruction_account(&transaction_context, 1)
+            .unwrap();
+        assert!(account.is_default());
+        drop(account);
+        let account = instruction_context
+            .try_borrow_instruction_account(&transaction_context, 2)
+            .unwrap();
+        assert!(!account.is_default());
+    }
+
+    #[test]
+    fn test_set_rent() {
+        let mut transaction_context = TransactionContext::new(
+            vec![(Pubkey::new_unique(), AccountSharedData::default())],
+            Rent::default(),
+            1,
+            1,
+        );
+        assert_eq!(
+            transaction_context.total_rent_exempt_minimum(),
+            Ok(Rent::default().minimum_balance(0)),
+        );
+        transaction_context.set_rent(Rent::free());
+        assert_eq!(transaction_context.total_rent_exempt_minimum(), Ok(0));
+    }
+
+    #[test]
+    fn test_require_signer_and_writable() {
+        let mut transaction_context = TransactionContext::new(
+            vec![
+                (Pubkey::new_unique(), AccountSharedData::default()),
+                (Pubkey::new_unique(), AccountSharedData::default()),
+                (Pubkey::new_unique(), AccountSharedData::default()),
+            ],
+            Rent::default(),
+            1,
+            1,
+        );
+        transaction_context
+            .get_next_instruction_context_mut()
+            .unwrap()
+            .configure(
+                vec![0],
+                vec![
+                    InstructionAccount::new(1, 0, true, false),
+                    InstructionAccount::new(2, 1, false, true),
+                ],
+                &[],
+            );
+        transaction_context.push().unwrap();
+        let instruction_context = transaction_context
+            .get_current_instruction_context()
+            .unwrap();
+
+        let account = instruction_context
+            .try_borrow_instruction_account(&transaction_context, 0)
+            .unwrap();
+        assert_eq!(account.require_signer(), Ok(()));
+        assert_eq!(
+            account.require_writable(),
+            Err(InstructionError::ReadonlyDataModified),
+        );
+        drop(account);
+
+        let account = instruction_context
+            .try_borrow_instruction_account(&transaction_context, 1)
+            .unwrap();
+        assert_eq!(
+            account.require_signer(),
+            Err(InstructionError::MissingRequiredSignature),
+        );
+        assert_eq!(account.require_writable(), Ok(()));
+    }
+
+    #[test]
+    fn test_read_return_data_into() {
+        let program_id = Pubkey::new_unique();
+        let mut transaction_context = TransactionContext::new(
+            vec![(program_id, AccountSharedData::default())],
+            Rent::default(),
+            1,
+            1,
+        );
+        transaction_context
+            .set_return_data(program_id, vec![1, 2, 3])
+            .unwrap();
+
+        let mut buf = [0; 2];
+        assert_eq!(
+            transaction_context.read_return_data_into(&mut buf),
+            (2, &program_id)
+        );
+        assert_eq!(buf, [1, 2]);
+
+        let mut buf = [0; 5];
+        assert_eq!(
+            transaction_context.read_return_data_into(&mut buf),
+            (3, &program_id)
+        );
+        assert_eq!(buf, [1, 2, 3, 0, 0]);
+
+        assert_eq!(
+            transaction_context.read_return_data_into(&mut []),
+            (0, &program_id)
+        );
+    }
+
+    #[test]
+    fn test_instruction_account_keys() {
+        let keys: [Pubkey; 3] = std::array::from_fn(|_| Pubkey::new_unique());
+        let [_, key_a, key_b] = keys;
+        let transaction_context = TransactionContext::new(
+            keys.iter()
+                .map(|key| (*key, AccountSharedData::default()))
+                .collect(),
+            Rent::default(),
+            1,
+            1,
+        );
+        let instruction_context = InstructionContext::new_for_tests(
+            0,
+            vec![0],
+            vec![
+                InstructionAccount::new(2, 0, false, false),
+                InstructionAccount::new(1, 1, false, false),
+                InstructionAccount::new(2, 0, false, false),
+            ],
+            vec![],
+        );
+        assert_eq!(
+            instruction_context.instruction_account_keys(&transaction_context),
+            Ok(vec![key_b, key_a, key_b]),
+        );
+
+        let instruction_context = InstructionContext::new_for_tests(
+            0,
+            vec![0],
+            vec![InstructionAccount::new(3, 0, false, false)],
+            vec![],
+        );
+        assert_eq!(
+            instruction_context.instruction_account_keys(&transaction_context),
+            Err(InstructionError::NotEnoughAccountKeys),
+        );
+    }
+
+    #[test]
+    fn test_get_program_owner() {
+        let loader_id = Pubkey::new_unique();
+        let transaction_context = TransactionContext::new(
+            vec![
+                (loader_id, AccountSharedData::default()),
+                (
+                    Pubkey::new_unique(),
+                    AccountSharedData::new(1, 0, &loader_id),
+                ),
+            ],
+            Rent::default(),
+            1,
+            1,
+        );
+        let instruction_context = InstructionContext::new_for_tests(0, vec![0, 1], vec![], vec![]);
+        assert_eq!(
+            instruction_context.get_program_owner(&transaction_context),
+            Ok(loader_id),
+        );
+
+        let instruction_context = InstructionContext::new_for_tests(0, vec![], vec![], vec![]);
+        assert_eq!(
+            instruction_context.get_program_owner(&transaction_context),
+            Err(InstructionError::NotEnoughAccountKeys),
+        );
+    }
+
+    #[cfg(feature = "bincode")]
+    #[test]
+    fn test_versioned_state_serde_round_trip() {
+        let program_id = Pubkey::new_unique();
+        let mut data = vec![1];
+        data.extend_from_slice(&bincode::serialize(&42u64).unwrap());
+        let mut transaction_context = TransactionContext::new(
+            vec![
+                (program_id, AccountSharedData::default()),
+                (
+                    Pubkey::new_unique(),
+                    AccountSharedData::create(1, data, program_id, false, 0),
+                ),
+                (
+                    Pubkey::new_unique(),
+                    AccountSharedData::new(1, 0, &program_id),
+                ),
+            ],
+            Rent::default(),
+            1,
+            1,
+        );
+        transaction_context
+            .get_next_instruction_context_mut()
+            .unwrap()
+            .configure(
+                vec![0],
+                vec![
+                    InstructionAccount::new(1, 0, false, false),
+                    InstructionAccount::new(2, 1, false, false),
+                ],
+                &[],
+            );
+        transaction_context.push().unwrap();
+        let instruction_context = transaction_context
+            .get_current_instruction_context()
+            .unwrap();
+
+        let account = instruction_context
+            .try_borrow_instruction_account(&transaction_context, 0)
+            .unwrap();
+        assert_eq!(account.get_versioned_state::<u64>(1), Ok(42));
+        assert_eq!(
+            account.get_versioned_state::<u64>(2),
+            Err(InstructionError::InvalidAccountData),
+        );
+        drop(account);
+
+        let account = instruction_context
+            .try_borrow_instruction_account(&transaction_context, 1)
+            .unwrap();
+        assert_eq!(
+            account.get_versioned_state::<u64>(1),
+            Err(InstructionError::InvalidAccountData),
+        );
+    }
+
+    #[test]
+    fn test_is_first_top_level_instruction() {
+        let mut transaction_context = TransactionContext::new(
+            vec![(Pubkey::new_unique(), AccountSharedData::default())],
+            Rent::default(),
+            2,
+            3,
+        );
+        let push = |transaction_context: &mut TransactionContext| {
+            transaction_context
+                .get_next_instruction_context_mut()
+                .unwrap()
+                .configure(vec![0], vec![], &[]);
+            transaction_context.push().unwrap();
+        };
+        assert!(!transaction_context.is_first_top_level_instruction());
+        push(&mut transaction_context);
+        assert!(transaction_context.is_first_top_level_instruction());
+        push(&mut transaction_context);
+        assert!(!transaction_context.is_first_top_level_instruction());
+        transaction_context.pop().unwrap();
+        assert!(transaction_context.is_first_top_level_instruction());
+        transaction_context.pop().unwrap();
+        push(&mut transaction_context);
+        assert!(!transaction_context.is_first_top_level_instruction());
+    }
+
+    #[test]
+    fn test_total_rent_exempt_minimum() {
+        let rent = Rent::default();
+        let transaction_context = TransactionContext::new(
+            vec![
+                (Pubkey::new_unique(), AccountSharedData::default()),
+                (
+                    Pubkey::new_unique(),
+                    AccountSharedData::new(1, 10, &Pubkey::new_unique()),
+                ),
+                (
+                    Pubkey::new_unique(),
+                    AccountSharedData::new(1, 100, &Pubkey::new_unique()),
+                ),
+            ],
+            rent.clone(),
+            1,
+            1,
+        );
+        assert_eq!(
+            transaction_context.total_rent_exempt_minimum(),
+            Ok(rent
+                .minimum_balance(0)
+                .saturating_add(rent.minimum_balance(10))
+                .saturating_add(rent.minimum_balance(100))),
+        );
+    }
+
+    #[test]
+    fn test_cpi_count_for_program() {
+        let program_keys: [Pubkey; 2] = std::array::from_fn(|_| Pubkey::new_unique());
+        let [program_a, program_b] = program_keys;
+        let mut transaction_context = TransactionContext::new(
+            program_keys
+                .iter()
+                .map(|key| (*key, AccountSharedData::default()))
+                .collect(),
+            Rent::default(),
+            3,
+            6,
+        );
+        let push = |transaction_context: &mut TransactionContext,
+                    program_account: IndexOfAccount| {
+            transaction_context
+                .get_next_instruction_context_mut()
+                .unwrap()
+                .configure(vec![program_account], vec![], &[]);
+            transaction_context.push().unwrap();
+        };
+        // A { B { A } B } A
+        push(&mut transaction_context, 0);
+        push(&mut transaction_context, 1);
+        push(&mut transaction_context, 0);
+        transaction_context.pop().unwrap();
+        transaction_context.pop().unwrap();
+        push(&mut transaction_context, 1);
+        transaction_context.pop().unwrap();
+        transaction_context.pop().unwrap();
+        push(&mut transaction_context, 0);
+        transaction_context.pop().unwrap();
+
+        assert_eq!(transaction_context.cpi_count_for_program(&program_a), 1);
+        assert_eq!(transaction_context.cpi_count_for_program(&program_b), 2);
+        assert_eq!(
+            transaction_context.cpi_count_for_program(&Pubkey::new_unique()),
+            0
+        );
+    }
+
+    #[test]
+    fn test_get_caller_program_key() {
+        let program_keys: [Pubkey; 2] = std::array::from_fn(|_| Pubkey::new_unique());
+        let [program_a, program_b] = program_keys;
+        let mut transaction_context = TransactionContext::new(
+            program_keys
+                .iter()
+                .map(|key| (*key, AccountSharedData::default()))
+                .collect(),
+            Rent::default(),
+            3,
+            3,
+        );
+        let push = |transaction_context: &mut TransactionContext,
+                    program_account: IndexOfAccount| {
+            transaction_context
+                .get_next_instruction_context_mut()
+                .unwrap()
+                .configure(vec![program_account], vec![], &[]);
+            transaction_context.push().unwrap();
+        };
+        assert_eq!(transaction_context.get_caller_program_key(), Ok(None));
+        push(&mut transaction_context, 0);
+        assert_eq!(transaction_context.get_caller_program_key(), Ok(None));
+        push(&mut transaction_context, 1);
+        assert_eq!(
+            transaction_context.get_caller_program_key(),
+            Ok(Some(&program_a))
+        );
+        push(&mut transaction_context, 0);
+        assert_eq!(
+            transaction_context.get_caller_program_key(),
+            Ok(Some(&program_b))
+        );
+    }
+
+    #[test]
+    fn test_native_program_account_indices() {
+        let native_loader_id = solana_sdk_ids::native_loader::id();
+        let transaction_context = TransactionContext::new(
+            vec![
+                (
+                    Pubkey::new_unique(),
+                    AccountSharedData::new(1, 0, &native_loader_id),
+                ),
+                (Pubkey::new_unique(), AccountSharedData::default()),
+                (
+                    Pubkey::new_unique(),
+                    AccountSharedData::new(1, 0, &native_loader_id),
+                ),
+            ],
+            Rent::default(),
+            1,
+            1,
+        );
+        assert_eq!(transaction_context.has_native_program_accounts(), Ok(true));
+        assert_eq!(
+            transaction_context.native_program_account_indices(),
+            Ok(vec![0, 2])
+        );
+
+        let transaction_context = TransactionContext::new(
+            vec![(Pubkey::new_unique(), AccountSharedData::default())],
+            Rent::default(),
+            1,
+            1,
+        );
+        assert_eq!(transaction_context.has_native_program_accounts(), Ok(false));
+        assert!(transaction_context
+            .native_program_account_indices()
+            .unwrap()
+            .is_empty());
+    }
+
+    #[test]
+    fn test_return_data_program_index() {
+        let program_id = Pubkey::new_unique();
+        let mut transaction_context = TransactionContext::new(
+            vec![
+                (Pubkey::new_unique(), AccountSharedData::default()),
+                (program_id, AccountSharedData::default()),
+            ],
+            Rent::default(),
+            1,
+            1,
+        );
+        assert_eq!(transaction_context.return_data_program_index(), None);
+        transaction_context
+            .set_return_data(program_id, vec![1, 2, 3])
+            .unwrap();
+        assert_eq!(transaction_context.return_data_program_index(), Some(1));
+        transaction_context
+            .set_return_data(Pubkey::new_unique(), vec![1, 2, 3])
+            .unwrap();
+        assert_eq!(transaction_context.return_data_program_index(), None);
+    }
+
+    #[test]
+    fn test_instruction_stack() {
+        let mut transaction_context = TransactionContext::new(
+            vec![(Pubkey::new_unique(), AccountSharedData::default())],
+            Rent::default(),
+            2,
+            3,
+        );
+        let push = |transaction_context: &mut TransactionContext| {
+            transaction_context
+                .get_next_instruction_context_mut()
+                .unwrap()
+                .configure(vec![0], vec![], &[]);
+            transaction_context.push().unwrap();
+        };
+        assert!(transaction_context.instruction_stack().is_empty());
+        push(&mut transaction_context);
+        push(&mut transaction_context);
+        assert_eq!(transaction_context.instruction_stack(), &[0, 1]);
+        transaction_context.pop().unwrap();
+        push(&mut transaction_context);
+        assert_eq!(transaction_context.instruction_stack(), &[0, 2]);
+    }
+
+    #[test]
+    fn test_check_max_number_of_instruction_accounts() {
+        let instruction_context = InstructionContext::new_for_tests(
+            0,
+            vec![0],
+            vec![
+                InstructionAccount::new(1, 0, false, false),
+                InstructionAccount::new(2, 1, false, false),
+            ],
+            vec![],
+        );
+        assert_eq!(
+            instruction_context.check_max_number_of_instruction_accounts(2),
+            Ok(())
+        );
+        assert_eq!(
+            instruction_context.check_max_number_of_instruction_accounts(1),
+            Err(InstructionError::MaxAccountsExceeded),
+        );
+    }
+
+    #[test]
+    fn test_owner_and_executable() {
+        let loader_id = Pubkey::new_unique();
+        let mut transaction_context = TransactionContext::new(
+            vec![
+                (loader_id, AccountSharedData::default()),
+                (
+                    Pubkey::new_unique(),
+                    AccountSharedData::create(1, vec![], loader_id, true, 0),
+                ),
+            ],
+            Rent::default(),
+            1,
+            1,
+        );
+        transaction_context.set_remove_accounts_executable_flag_checks(true);
+        transaction_context
+            .get_next_instruction_context_mut()
+            .unwrap()
+            .configure(
+                vec![0],
+                vec![InstructionAccount::new(1, 0, false, false)],
+                &[],
+            );
+        transaction_context.push().unwrap();
+        let instruction_context = transaction_context
+            .get_current_instruction_context()
+            .unwrap();
+        let account = instruction_context
+            .try_borrow_instruction_account(&transaction_context, 0)
+            .unwrap();
+        assert_eq!(account.owner_and_executable(), (&loader_id, true));
+    }
+
+    #[test]
+    fn test_resized_account_count() {
+        let program_id = Pubkey::new_unique();
+        let mut transaction_context = TransactionContext::new(
+            vec![
+                (program_id, AccountSharedData::default()),
+                (
+                    Pubkey::new_unique(),
+                    AccountSharedData::new(1, 3, &program_id),
+                ),
+                (
+                    Pubkey::new_unique(),
+                    AccountSharedData::new(1, 3, &program_id),
+                ),
+            ],
+            Rent::default(),
+            1,
+            1,
+        );
+        transaction_context
+            .get_next_instruction_context_mut()
+            .unwrap()
+            .configure(
+                vec![0],
+                vec![
+                    InstructionAccount::new(1, 0, false, true),
+                    InstructionAccount::new(2, 1, false, true),
+                ],
+                &[],
+            );
+        transaction_context.push().unwrap();
+        assert_eq!(transaction_context.resized_account_count(), Ok(0));
+
+        let instruction_context = transaction_context
+            .get_current_instruction_context()
+            .unwrap();
+        let mut account = instruction_context
+            .try_borrow_instruction_account(&transaction_context, 0)
+            .unwrap();
+        account.set_data_length(5).unwrap();
+        drop(account);
+        let mut account = instruction_context
+            .try_borrow_instruction_account(&transaction_context, 1)
+            .unwrap();
+        account.set_data_length(1).unwrap();
+        drop(account);
+        assert_eq!(transaction_context.resized_account_count(), Ok(2));
+
+        let mut account = instruction_context
+            .try_borrow_instruction_account(&transaction_context, 0)
+            .unwrap();
+        account.set_data_length(3).unwrap();
+        drop(account);
+        assert_eq!(transaction_context.resized_account_count(), Ok(1));
+    }
+
+    #[test]
+    fn test_require_all_signers() {
+        let keys: [Pubkey; 4] = std::array::from_fn(|_| Pubkey::new_unique());
+        let [_, signer_a, signer_b, non_signer] = keys;
+        let transaction_context = TransactionContext::new(
+            keys.iter()
+                .map(|key| (*key, AccountSharedData::default()))
+                .collect(),
+            Rent::default(),
+            1,
+            1,
+        );
+        let instruction_context = InstructionContext::new_for_tests(
+            0,
+            vec![0],
+            vec![
+                InstructionAccount::new(1, 0, true, false),
+                InstructionAccount::new(2, 1, true, false),
+                InstructionAccount::new(3, 2, false, false),
+            ],
+            vec![],
+        );
+        assert_eq!(
+            instruction_context.require_all_signers(&transaction_context, &[]),
+            Ok(())
+        );
+        assert_eq!(
+            instruction_context.require_all_signers(&transaction_context, &[signer_b, signer_a]),
+            Ok(())
+        );
+        assert_eq!(
+            instruction_context.require_all_signers(&transaction_context, &[signer_a, non_signer]),
+            Err(InstructionError::MissingRequiredSignature),
+        );
+    }
+
+    #[test]
+    fn test_current_program_index() {
+        let mut transaction_context = TransactionContext::new(
+            vec![
+                (Pubkey::new_unique(), AccountSharedData::default()),
+                (Pubkey::new_unique(), AccountSharedData::default()),
+            ],
+            Rent::default(),
+            1,
+            1,
+        );
+        assert_eq!(
+            transaction_context.current_program_index(),
+            Err(InstructionError::CallDepth),
+        );
+        transaction_context
+            .get_next_instruction_context_mut()
+            .unwrap()
+            .configure(vec![0, 1], vec![], &[]);
+        transaction_context.push().unwrap();
+        assert_eq!(transaction_context.current_program_index(), Ok(1));
+    }
+
+    #[test]
+    fn test_reserve_writable_capacity() {
+        let program_id = Pubkey::new_unique();
+        let shared_account = AccountSharedData::create(1, vec![1, 2, 3], program_id, false, 0);
+        let mut transaction_context = TransactionContext::new(
+            vec![
+                (program_id, AccountSharedData::default()),
+                (Pubkey::new_unique(), shared_account.clone()),
+                (
+                    Pubkey::new_unique(),
+                    AccountSharedData::new(1, 3, &program_id),
+                ),
+                (
+                    Pubkey::new_unique(),
+                    AccountSharedData::new(1, 3, &program_id),
+                ),
+                (
+                    Pubkey::new_unique(),
+                    AccountSharedData::new(1, 0, &program_id),
+                ),
+                (
+                    Pubkey::new_unique(),
+                    AccountSharedData::new(1, 0, &program_id),
+                ),
+                (
+                    Pubkey::new_unique(),
+                    AccountSharedData::new(1, 0, &program_id),
+                ),
+            ],
+            Rent::default(),
+            1,
+            1,
+        );
+        transaction_context.set_account_max_length(2, 5).unwrap();
+        transaction_context
+            .get_next_instruction_context_mut()
+            .unwrap()
+            .configure(
+                vec![0],
+                vec![
+                    InstructionAccount::new(1, 0, false, true),
+                    InstructionAccount::new(2, 1, false, true),
+                    InstructionAccount::new(3, 2, false, false),
+                    InstructionAccount::new(4, 3, false, true),
+                    InstructionAccount::new(5, 4, false, true),
+                    InstructionAccount::new(6, 5, false, true),
+                ],
+                &[],
+            );
+        transaction_context.push().unwrap();
+        let capacity = |index_in_transaction| {
+            transaction_context
+                .peek_account(index_in_transaction)
+                .unwrap()
+                .capacity()
+        };
+        let readonly_capacity = capacity(3);
+
+        // Only the capacity grows, and shared accounts are unshared
+        assert_eq!(transaction_context.count_shared_accounts(), Ok((1, 6)));
+        assert_eq!(transaction_context.reserve_writable_capacity(100), Ok(()));
+        assert!(capacity(1) >= 103);
+        assert_eq!(
+            transaction_context.peek_account(1).unwrap().data(),
+            &[1, 2, 3]
+        );
+        assert_eq!(transaction_context.count_shared_accounts(), Ok((0, 7)));
+        assert_eq!(shared_account.data(), &[1, 2, 3]);
+        assert_eq!(capacity(3), readonly_capacity);
+        assert_eq!(transaction_context.accounts_resize_delta(), Ok(0));
+
+        // Capped by the maximum length of the account
+        assert!(capacity(2) >= 5);
+        assert!(capacity(2) < 103);
+
+        // Fails if a writable account is borrowed
+        let instruction_context = transaction_context
+            .get_current_instruction_context()
+            .unwrap();
+        let mut account = instruction_context
+            .try_borrow_instruction_account(&transaction_context, 3)
+            .unwrap();
+        assert_eq!(
+            transaction_context.reserve_writable_capacity(100),
+            Err(InstructionError::AccountBorrowFailed),
+        );
+        account
+            .set_data_length(MAX_PERMITTED_DATA_LENGTH as usize)
+            .unwrap();
+        drop(account);
+
+        // Capped by the remaining resize budget
+        let mut account = instruction_context
+            .try_borrow_instruction_account(&transaction_context, 4)
+            .unwrap();
+        account
+            .set_data_length(MAX_PERMITTED_DATA_LENGTH as usize)
+            .unwrap();
+        drop(account);
+        assert_eq!(transaction_context.remaining_resize_budget(), Ok(0));
+        let exhausted_capacity = capacity(6);
+        assert_eq!(transaction_context.reserve_writable_capacity(100), Ok(()));
+        assert_eq!(capacity(6), exhausted_capacity);
+        assert_eq!(
+            transaction_context.accounts_resize_delta(),
+            Ok(MAX_PERMITTED_ACCOUNTS_DATA_ALLOCATIONS_PER_TRANSACTION),
+        );
+    }
+
+    #[test]
+    fn test_account_data_lengths() {
+        let owner = Pubkey::new_unique();
+        let transaction_context = TransactionContext::new(
+            [3, 0, 7]
+                .into_iter()
+                .map(|space| {
+                    (
+                        Pubkey::new_unique(),
+                        AccountSharedData::new(1, space, &owner),
+                    )
+                })
+                .collect(),
+            Rent::default(),
+            1,
+            1,
+        );
+        assert_eq!(
+            transaction_context.account_data_lengths(),
+            Ok(vec![3, 0, 7])
+        );
+
+        let account = transaction_context
+            .try_borrow_account_mut_by_index(1)
+            .unwrap();
+        assert_eq!(
+            transaction_context.account_data_lengths(),
+            Err(InstructionError::AccountBorrowFailed),
+        );
+        drop(account);
+        assert_eq!(
+            transaction_context.account_data_lengths(),
+            Ok(vec![3, 0, 7])
+        );
+    }
+
+    #[test]
+    fn test_return_data_is_empty() {
+        let mut transaction_context = TransactionContext::new(
+            vec![(Pubkey::new_unique(), AccountSharedData::default())],
+            Rent::default(),
+            /* max_instruction_stack_depth */ 1,
+            /* max_instruction_trace_length */ 1,
+        );
+        assert!(TransactionReturnData::default().is_empty());
+
+        let program_id = Pubkey::new_unique();
+        transaction_context
+            .set_return_data(program_id, vec![1, 2, 3])
+            .unwrap();
+        let return_data = ExecutionRecord::from(transaction_context).return_data;
+        assert!(!return_data.is_empty());
+
+        let mut transaction_context = TransactionContext::new(
+            vec![(Pubkey::new_unique(), AccountSharedData::default())],
+            Rent::default(),
+            /* max_instruction_stack_depth */ 1,
+            /* max_instruction_trace_length */ 1,
+        );
+        transaction_context
+            .set_return_data(program_id, Vec::new())
+            .unwrap();
+        assert_eq!(
+            transaction_context.get_return_data(),
+            (&program_id, &[][..])
+        );
+        let return_data = ExecutionRecord::from(transaction_context).return_data;
+        assert_eq!(return_data.program_id, program_id);
+        assert!(return_data.is_empty());
+    }
 }